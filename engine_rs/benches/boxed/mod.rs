@@ -0,0 +1,150 @@
+use engine_rs::Real;
+use engine_rs::quadtree::{Point, Rectangle};
+use engine_rs::vector::Particle;
+
+/*
+The quadtree as it was before the flat arena (commit a4954a8): every node owns its children
+in Boxes and its points in a Vec, and the force walk recurses. Kept here, trimmed to what the
+tree bench times (unit masses, geometric opening, monopole), as the baseline for the
+build and force columns of the flat tree
+ */
+
+const MAX_DEPTH: u32 = 32;
+const MIN_SIZE: Real = 0.01;
+const THRESHOLD: usize = 4;
+
+pub struct BoxTree {
+    area: Rectangle,
+    zones: [Option<Box<BoxTree>>; 4],
+    elements: Vec<Point>,
+    mass: Real,
+    cm: Point,
+    quad: [Real; 3], // quadrupole tensor about cm: [Qxx, Qxy, Qyy]
+}
+
+impl BoxTree {
+    fn new(area: Rectangle) -> Self {
+        BoxTree { area, zones: [None, None, None, None], elements: Vec::new(), mass: 0.0, cm: Point::zero(), quad: [0.0; 3] }
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.zones.iter().all(|z| z.is_none())
+    }
+
+    fn insert(&mut self, p: Point, depth: u32) {
+        if !self.area.contains(&p) {
+            return;
+        }
+        let size: Point = self.area.size();
+        if depth >= MAX_DEPTH || size.x <= MIN_SIZE || size.y <= MIN_SIZE {
+            self.elements.push(p);
+            return;
+        }
+        if self.is_leaf() && self.elements.len() < THRESHOLD {
+            self.elements.push(p);
+            return;
+        }
+        if self.is_leaf() {
+            self.subdivide();
+            for q in std::mem::take(&mut self.elements) {
+                self.insert(q, depth + 1);
+            }
+        }
+        for child in self.zones.iter_mut().filter_map(|z| z.as_deref_mut()) {
+            if child.area.contains(&p) {
+                child.insert(p, depth + 1);
+                return;
+            }
+        }
+        // on a split line, kept here
+        self.elements.push(p);
+    }
+
+    fn subdivide(&mut self) {
+        let (p1, p2) = (self.area.p1, self.area.p2);
+        let mid: Point = Point { x: (p1.x + p2.x) * 0.5, y: (p1.y + p2.y) * 0.5 };
+        self.zones = [
+            Some(Box::new(BoxTree::new(Rectangle::new(Point::new(p1.x, mid.y), Point::new(mid.x, p2.y))))),
+            Some(Box::new(BoxTree::new(Rectangle::new(mid, p2)))),
+            Some(Box::new(BoxTree::new(Rectangle::new(p1, mid)))),
+            Some(Box::new(BoxTree::new(Rectangle::new(Point::new(mid.x, p1.y), Point::new(p2.x, mid.y))))),
+        ];
+    }
+
+    fn compute_moments(&mut self) {
+        let mut mass: Real = self.elements.len() as Real;
+        let mut weighted: Point = Point::zero();
+        for q in &self.elements {
+            weighted = weighted.add(q);
+        }
+        for child in self.zones.iter_mut().filter_map(|z| z.as_deref_mut()) {
+            child.compute_moments();
+            mass += child.mass;
+            weighted = weighted.add(&child.cm.mult_scalar(child.mass));
+        }
+        self.mass = mass;
+        self.quad = [0.0; 3];
+        if mass == 0.0 {
+            self.cm = Point::zero();
+            return;
+        }
+        self.cm = weighted.div(mass);
+        for q in &self.elements {
+            add_quadrupole(&mut self.quad, 1.0, q.x - self.cm.x, q.y - self.cm.y);
+        }
+        for child in self.zones.iter().filter_map(|z| z.as_deref()) {
+            if child.mass == 0.0 {
+                continue;
+            }
+            self.quad[0] += child.quad[0];
+            self.quad[1] += child.quad[1];
+            self.quad[2] += child.quad[2];
+            add_quadrupole(&mut self.quad, child.mass, child.cm.x - self.cm.x, child.cm.y - self.cm.y);
+        }
+    }
+}
+
+fn add_quadrupole(quad: &mut [Real; 3], mass: Real, sx: Real, sy: Real) {
+    let s2: Real = sx * sx + sy * sy;
+    quad[0] += mass * (3.0 * sx * sx - s2);
+    quad[1] += mass * 3.0 * sx * sy;
+    quad[2] += mass * (3.0 * sy * sy - s2);
+}
+
+pub fn build(particles: &[Particle], bounds: Rectangle) -> BoxTree {
+    let mut tree: BoxTree = BoxTree::new(bounds);
+    for p in particles {
+        tree.insert(p.position, 0);
+    }
+    tree.compute_moments();
+    tree
+}
+
+fn pull(p: Point, q: Point, mass: Real, g: Real, eps2: Real) -> Point {
+    let (dx, dy) = (q.x - p.x, q.y - p.y);
+    let r2: Real = dx * dx + dy * dy + eps2;
+    let inv_r3: Real = 1.0 / (r2 * r2.sqrt());
+    Point { x: g * mass * dx * inv_r3, y: g * mass * dy * inv_r3 }
+}
+
+pub fn force(p: Point, node: &BoxTree, theta: Real, g: Real, eps2: Real) -> Point {
+    /*
+    the recursive walk, skipping the particle itself by position like it used to
+     */
+    let points = |node: &BoxTree| {
+        node.elements.iter().filter(|q| q.x != p.x || q.y != p.y).fold(Point::zero(), |f, &q| f.add(&pull(p, q, 1.0, g, eps2)))
+    };
+    if node.is_leaf() {
+        return points(node);
+    }
+    if node.mass == 0.0 {
+        return Point::zero();
+    }
+    let size: Point = node.area.size();
+    let (dx, dy) = (node.cm.x - p.x, node.cm.y - p.y);
+    if !node.area.contains(&p) && size.x.max(size.y) / (dx * dx + dy * dy + eps2).sqrt() < theta {
+        return pull(p, node.cm, node.mass, g, eps2);
+    }
+    // points that landed exactly on a split line are kept in the internal node
+    node.zones.iter().filter_map(|z| z.as_deref()).fold(points(node), |f, child| f.add(&force(p, child, theta, g, eps2)))
+}
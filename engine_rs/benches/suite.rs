@@ -0,0 +1,208 @@
+use engine_rs::Real;
+use engine_rs::barnes_hut::{Execution, Multipole, Opening, SimParams, TreeParams, step_barnes_hut, tree_force};
+use engine_rs::collision::{collide_all_pairs, collide_banded};
+use engine_rs::quadtree::{Point, QuadTree, Rectangle, build_tree};
+use engine_rs::rng::seed_from_args;
+use engine_rs::scenario::{Distribution, generate};
+use engine_rs::softening::Softening;
+use engine_rs::vector::Particle;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/*
+Benchmark suite: times each phase of a step (tree build, force loop, collision phase) and the
+full step across particle counts, theta values, opening criteria and initial distributions.
+
+    cargo bench --bench suite            # full grid
+    cargo bench --bench suite -- --quick # n <= 10k, theta 0.7 only (every opening criterion)
+    cargo bench --bench suite -- --seed 7
+
+Every row is printed and appended to bench_results.csv in the crate root (override with
+BENCH_OUT) together with the commit, time, float type and seed, so runs can be compared over time.
+Phases that are O(n^2) are skipped above PAIRS_MAX particles and left empty, and so are the
+force errors, which are measured against the direct sum.
+ */
+
+const HEADER: &str = "commit,unix_time,real,threads,seed,distribution,n,theta,opening,build_ms,force_ms,\
+collide_pairs_ms,collide_grid_ms,step_serial_ms,step_parallel_ms,interactions_per_particle,\
+err_monopole,err_quadrupole";
+const OPENINGS: [Opening; 4] = [
+    Opening::Geometric,
+    Opening::NearestEdge,
+    Opening::SalmonWarren { delta: 0.01 },
+    Opening::Relative { alpha: 0.005 },
+];
+const PAIRS_MAX: usize = 10_000;
+const RADIUS: Real = 1.0;
+const E: Real = 0.99;
+const MU: Real = 0.0; // frictionless, like the viewer
+const DT: Real = 1.0 / 15.0;
+
+fn commit() -> String {
+    Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn opening_name(opening: Opening) -> String {
+    match opening {
+        Opening::Geometric => "geometric".to_string(),
+        Opening::NearestEdge => "nearest_edge".to_string(),
+        Opening::SalmonWarren { delta } => format!("salmon_warren({delta})"),
+        Opening::Relative { alpha } => format!("relative({alpha})"),
+    }
+}
+
+fn forces(particles: &[Particle], tree: &QuadTree, params: &TreeParams, a_old: &[Real]) -> Vec<Point> {
+    let mut interactions: u32 = 0;
+    (0..particles.len())
+        .map(|i| tree_force(particles[i].position, Some(i as u32), tree, &[], params, a_old[i], &mut interactions))
+        .collect()
+}
+
+fn mean_error(approx: &[Point], exact: &[Point]) -> Real {
+    // mean of |approx - exact| / |exact|
+    let total: Real = approx.iter().zip(exact).map(|(a, e)| a.distance(e) / e.distance(&Point::zero())).sum();
+    total / exact.len() as Real
+}
+
+fn ms(d: Duration, reps: u32) -> f64 {
+    d.as_secs_f64() * 1e3 / reps as f64
+}
+
+fn time_collisions(particles: &[Particle], reps: u32, collide: impl Fn(&mut [Particle]) -> u32) -> f64 {
+    // every rep starts from the same state; the copy is not timed
+    let mut total: Duration = Duration::ZERO;
+    for _ in 0..reps {
+        let mut work: Vec<Particle> = particles.to_vec();
+        let start: Instant = Instant::now();
+        std::hint::black_box(collide(&mut work));
+        total += start.elapsed();
+    }
+    ms(total, reps)
+}
+
+fn time_steps(particles: &[Particle], bounds: Rectangle, params: &TreeParams, exec: Execution, reps: u32) -> f64 {
+    // full frame as the viewer runs it: rebuild the tree, then step
+    let mut work: Vec<Particle> = particles.to_vec();
+    let mut tree: QuadTree = QuadTree::new(bounds);
+    tree.parallel = exec != Execution::Serial;
+    let mut sim: SimParams = SimParams::default();
+    let start: Instant = Instant::now();
+    for k in 0..reps {
+        tree.rebuild_morton(&work, bounds);
+        std::hint::black_box(step_barnes_hut(&mut work, &tree, k as Real * DT, DT, params, &mut sim, exec));
+    }
+    ms(start.elapsed(), reps)
+}
+
+fn main() {
+    let seed: u64 = seed_from_args();
+    let quick: bool = std::env::args().any(|a| a == "--quick");
+    let ns: &[usize] = if quick { &[1_000, 10_000] } else { &[1_000, 10_000, 100_000] };
+    let thetas: &[Real] = if quick { &[0.7] } else { &[0.3, 0.7, 1.0] };
+    let dists: [Distribution; 3] = [Distribution::Uniform, Distribution::Clustered, Distribution::Disk];
+
+    let bounds: Rectangle = Rectangle::new(Point::new(-100.0, -75.0), Point::new(100.0, 75.0));
+    let real: &str = if size_of::<Real>() == 8 { "f64" } else { "f32" };
+    let commit: String = commit();
+    let unix_time: u64 = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let threads: usize = rayon::current_num_threads();
+
+    let path: PathBuf = std::env::var_os("BENCH_OUT")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("bench_results.csv"));
+    let fresh: bool = !path.exists();
+    let mut out = OpenOptions::new().create(true).append(true).open(&path).expect("couldn't open results file");
+    if fresh {
+        writeln!(out, "{HEADER}").expect("couldn't write results file");
+    }
+
+    println!("{HEADER}");
+    for &dist in &dists {
+        for &n in ns {
+            let particles: Vec<Particle> = generate(dist, n, bounds, seed);
+            let reps: u32 = (100_000 / n as u32).clamp(2, 50);
+
+            // build does not depend on theta
+            let start: Instant = Instant::now();
+            let mut tree: QuadTree = build_tree(&particles, bounds);
+            for _ in 1..reps {
+                tree.rebuild(&particles, bounds);
+            }
+            let build_ms: f64 = ms(start.elapsed(), reps);
+
+            // neither does the collision phase
+            let pairs_ms: Option<f64> =
+                (n <= PAIRS_MAX).then(|| time_collisions(&particles, reps, |p| collide_all_pairs(p, RADIUS, E, MU)));
+            let grid_ms: f64 = time_collisions(&particles, reps, |p| collide_banded(p, RADIUS, E, MU, 4));
+
+            // Relative needs last step's accelerations, a geometric walk stands in for them;
+            // theta = 0 opens every node, which is the direct sum
+            let base: TreeParams = TreeParams {
+                theta: 0.7,
+                g: 20.0,
+                coulomb: 0.0,
+                eps2: 1e-3,
+                softening: Softening::Plummer,
+                adaptive: None,
+                order: Multipole::Monopole,
+                opening: Opening::Geometric,
+                simd: false,
+            };
+            let zeros: Vec<Real> = vec![0.0; n];
+            let a_old: Vec<Real> = forces(&particles, &tree, &base, &zeros).iter().map(|a| a.distance(&Point::zero())).collect();
+            let exact: Option<Vec<Point>> =
+                (n <= PAIRS_MAX).then(|| forces(&particles, &tree, &TreeParams { theta: 0.0, ..base }, &zeros));
+
+            for (&theta, opening) in thetas.iter().flat_map(|t| OPENINGS.iter().map(move |&o| (t, o))) {
+                let params: TreeParams = TreeParams { theta, opening, ..base };
+
+                let mut interactions: u32 = 0;
+                let start: Instant = Instant::now();
+                for _ in 0..reps {
+                    interactions = 0;
+                    for (i, part) in particles.iter().enumerate() {
+                        let me: Option<u32> = Some(i as u32);
+                        std::hint::black_box(tree_force(part.position, me, &tree, &[], &params, a_old[i], &mut interactions));
+                    }
+                }
+                let force_ms: f64 = ms(start.elapsed(), reps);
+
+                let serial_ms: Option<f64> =
+                    (n <= PAIRS_MAX).then(|| time_steps(&particles, bounds, &params, Execution::Serial, reps));
+                let parallel_ms: f64 =
+                    time_steps(&particles, bounds, &params, Execution::Parallel { deterministic: true }, reps);
+
+                let errors: Option<(Real, Real)> = exact.as_ref().map(|exact| {
+                    let quadrupole: TreeParams = TreeParams { order: Multipole::Quadrupole, ..params };
+                    let monopole: Real = mean_error(&forces(&particles, &tree, &params, &a_old), exact);
+                    (monopole, mean_error(&forces(&particles, &tree, &quadrupole, &a_old), exact))
+                });
+
+                let opt = |v: Option<f64>| v.map(|v| format!("{v:.3}")).unwrap_or_default();
+                let err = |v: Option<Real>| v.map(|v| format!("{v:.1e}")).unwrap_or_default();
+                let row: String = format!(
+                    "{commit},{unix_time},{real},{threads},{seed},{},{n},{theta},{},{build_ms:.3},{force_ms:.3},{},{grid_ms:.3},{},{parallel_ms:.3},{:.1},{},{}",
+                    dist.name(),
+                    opening_name(opening),
+                    opt(pairs_ms),
+                    opt(serial_ms),
+                    interactions as f64 / n as f64,
+                    err(errors.map(|e| e.0)),
+                    err(errors.map(|e| e.1)),
+                );
+                println!("{row}");
+                writeln!(out, "{row}").expect("couldn't write results file");
+            }
+        }
+    }
+    eprintln!("appended to {}", path.display());
+}
@@ -0,0 +1,126 @@
+use engine_rs::Real;
+use engine_rs::barnes_hut::{Multipole, Opening, TreeParams, tree_force};
+use engine_rs::morton::sort_particles;
+use engine_rs::quadtree::{Point, QuadTree, Rectangle, build_tree};
+use engine_rs::rng::DEFAULT_SEED;
+use engine_rs::scenario::{Distribution, generate};
+use engine_rs::simd::{GROUP, InteractionList, ParticleSoa, eval_scalar, eval_simd, gather, simd_forces};
+use engine_rs::softening::Softening;
+use engine_rs::vector::Particle;
+use std::time::Instant;
+
+mod boxed;
+
+fn main() {
+    let bounds: Rectangle = Rectangle::new(Point::new(-100.0, -75.0), Point::new(100.0, 75.0));
+    let params: TreeParams = TreeParams {
+        theta: 0.7,
+        g: 20.0,
+        coulomb: 0.0,
+        eps2: 1e-3,
+        softening: Softening::Plummer,
+        adaptive: None,
+        order: Multipole::Monopole,
+        opening: Opening::Geometric,
+        simd: false,
+    };
+
+    println!("n,build_box_ms,force_box_ms,build_ms,morton_build_ms,force_ms,force_sorted_ms,force_simd_ms,eval_scalar_ms,eval_simd_ms,eval_scalar_spline_ms,eval_simd_spline_ms");
+    for n in [1_000, 10_000, 100_000] {
+        let mut particles: Vec<Particle> = generate(Distribution::Uniform, n, bounds, DEFAULT_SEED);
+        let reps: u32 = (200_000 / n as u32).max(3);
+
+        // the boxed tree the flat one replaced, as a baseline
+        let start: Instant = Instant::now();
+        let mut old: boxed::BoxTree = boxed::build(&particles, bounds);
+        for _ in 1..reps {
+            old = boxed::build(&particles, bounds);
+        }
+        let build_box_ms: f64 = start.elapsed().as_secs_f64() * 1e3 / reps as f64;
+
+        let mut sum: Real = 0.0;
+        let start: Instant = Instant::now();
+        for _ in 0..reps {
+            for part in &particles {
+                sum += boxed::force(part.position, &old, params.theta, params.g, params.eps2).x;
+            }
+        }
+        let force_box_ms: f64 = start.elapsed().as_secs_f64() * 1e3 / reps as f64;
+        drop(old);
+
+        let start: Instant = Instant::now();
+        let mut tree: QuadTree = build_tree(&particles, bounds);
+        for _ in 1..reps {
+            tree.rebuild(&particles, bounds);
+        }
+        let build_ms: f64 = start.elapsed().as_secs_f64() * 1e3 / reps as f64;
+
+        let start: Instant = Instant::now();
+        for _ in 0..reps {
+            tree.rebuild_morton(&particles, bounds);
+        }
+        let morton_ms: f64 = start.elapsed().as_secs_f64() * 1e3 / reps as f64;
+
+        let start: Instant = Instant::now();
+        let mut interactions: u32 = 0;
+        for _ in 0..reps {
+            for (i, part) in particles.iter().enumerate() {
+                let f: Point = tree_force(part.position, Some(i as u32), &tree, &[], &params, 0.0, &mut interactions);
+                sum += f.x;
+            }
+        }
+        let force_ms: f64 = start.elapsed().as_secs_f64() * 1e3 / reps as f64;
+
+        // same force loop with the particle array in Morton order (rebuilt so the leaves
+        // refer to the sorted particles, the tree itself doesn't change)
+        sort_particles(&mut particles, bounds);
+        tree.rebuild_morton(&particles, bounds);
+        let start: Instant = Instant::now();
+        for _ in 0..reps {
+            for (i, part) in particles.iter().enumerate() {
+                let f: Point = tree_force(part.position, Some(i as u32), &tree, &[], &params, 0.0, &mut interactions);
+                sum += f.x;
+            }
+        }
+        let sorted_ms: f64 = start.elapsed().as_secs_f64() * 1e3 / reps as f64;
+
+        // batched kernels on the same sorted particles
+        let mut list: InteractionList = InteractionList::new();
+        let mut soa: ParticleSoa = ParticleSoa::new();
+        soa.load(&particles, &tree);
+        let mut out: Vec<(Point, u32)> = Vec::with_capacity(n);
+        let start: Instant = Instant::now();
+        for _ in 0..reps {
+            out.clear();
+            simd_forces(&soa.x, &soa.y, &soa.a_old, &soa.id, &tree, &[], &params, &mut list, &mut out);
+            sum += out[0].0.x;
+        }
+        let simd_ms: f64 = start.elapsed().as_secs_f64() * 1e3 / reps as f64;
+
+        // the list evaluation alone (not the walks), in blocks against one interaction at a
+        // time, for Plummer and for the spline whose scalar form branches the most
+        let spline: TreeParams = TreeParams { softening: Softening::Spline, ..params };
+        let mut eval_ms: [f64; 4] = [0.0; 4];
+        for _ in 0..reps {
+            for start in (0..n).step_by(GROUP) {
+                let end: usize = (start + GROUP).min(n);
+                gather(&soa.x[start..end], &soa.y[start..end], &soa.a_old[start..end], &tree, &[], &params, &mut list);
+                for (slot, (kernel, blocked)) in [(&params, false), (&params, true), (&spline, false), (&spline, true)].into_iter().enumerate() {
+                    let timer: Instant = Instant::now();
+                    for i in start..end {
+                        let p: Point = Point::new(soa.x[i], soa.y[i]);
+                        let f: Point = if blocked { eval_simd(p, soa.id[i], &list, kernel) } else { eval_scalar(p, soa.id[i], &list, kernel) };
+                        sum += f.x;
+                    }
+                    eval_ms[slot] += timer.elapsed().as_secs_f64() * 1e3 / reps as f64;
+                }
+            }
+        }
+        std::hint::black_box(sum);
+
+        println!(
+            "{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3}",
+            n, build_box_ms, force_box_ms, build_ms, morton_ms, force_ms, sorted_ms, simd_ms, eval_ms[0], eval_ms[1], eval_ms[2], eval_ms[3]
+        );
+    }
+}
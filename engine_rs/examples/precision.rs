@@ -0,0 +1,74 @@
+use engine_rs::Real;
+use engine_rs::barnes_hut::{Execution, Multipole, Opening, SimParams, TreeParams, step_barnes_hut};
+use engine_rs::quadtree::{Point, QuadTree, Rectangle};
+use engine_rs::rng::seed_from_args;
+use engine_rs::scenario::{Distribution, generate};
+use engine_rs::snapshot::{read_snapshot, write_snapshot};
+use engine_rs::softening::Softening;
+use engine_rs::vector::Particle;
+use std::fs::File;
+use std::collections::HashMap;
+use std::io::BufReader;
+
+/*
+Runs the viewer's scene without a window and writes a snapshot of the final state to precision_<f32|f64>.csv.
+Run it once with and once without `--features f64` (and the same `--seed`, if any), the second
+run compares against the first:
+    cargo run --release --example precision
+    cargo run --release --example precision --features f64
+ */
+#[allow(clippy::useless_conversion)] // Real is already f64 with the f64 feature
+fn main() {
+    let bounds: Rectangle = Rectangle::new(Point::new(-100.0, -75.0), Point::new(100.0, 75.0));
+    let n: usize = 1000;
+    let steps: usize = 120;
+    let seed: u64 = seed_from_args();
+
+    let mut particles: Vec<Particle> = generate(Distribution::Uniform, n, bounds, seed);
+
+    let params: TreeParams = TreeParams {
+        theta: 0.7,
+        g: 20.0,
+        coulomb: 0.0,
+        eps2: 1e-3,
+        softening: Softening::Plummer,
+        adaptive: None,
+        order: Multipole::Monopole,
+        opening: Opening::Geometric,
+        simd: false,
+    };
+    let mut sim: SimParams = SimParams::default();
+    let dt: Real = 1.0 / 15.0;
+    let mut tree: QuadTree = QuadTree::new(bounds);
+    for k in 0..steps {
+        tree.rebuild(&particles, bounds);
+        step_barnes_hut(&mut particles, &tree, k as Real * dt, dt, &params, &mut sim, Execution::Serial);
+    }
+
+    let (name, other) = if std::mem::size_of::<Real>() == 8 { ("f64", "f32") } else { ("f32", "f64") };
+    let mut out: File = File::create(format!("precision_{name}.csv")).expect("failed to create csv");
+    write_snapshot(&mut out, seed, steps as u64, &particles).unwrap();
+    println!("wrote precision_{name}.csv after {steps} steps");
+
+    let Ok(file) = File::open(format!("precision_{other}.csv")) else {
+        return;
+    };
+    let theirs: HashMap<u64, Particle> =
+        read_snapshot(BufReader::new(file)).unwrap().into_iter().map(|p| (p.id, p)).collect();
+    let mut sum2: f64 = 0.0;
+    let mut worst: f64 = 0.0;
+    for p in &particles {
+        // matched by id, so a different particle order in the other run doesn't matter
+        let q: &Particle = &theirs[&p.id];
+        let dx: f64 = f64::from(q.position.x) - f64::from(p.position.x);
+        let dy: f64 = f64::from(q.position.y) - f64::from(p.position.y);
+        let d2: f64 = dx * dx + dy * dy;
+        sum2 += d2;
+        worst = worst.max(d2.sqrt());
+    }
+    println!(
+        "{name} vs {other}: rms position difference {:.4}, max {:.4}",
+        (sum2 / n as f64).sqrt(),
+        worst
+    );
+}
@@ -0,0 +1,174 @@
+use crate::Real;
+use crate::barnes_hut::accel_toward_point;
+use crate::field::ForceField;
+use crate::quadtree::Point;
+use crate::softening::Softening;
+use crate::vector::Vector;
+
+#[derive(Debug, Clone)]
+pub enum Path {
+    /*
+    Prescribed motion of an attractor. It is not pulled by the particles or the other attractors
+     */
+    Fixed(Point),
+    Circular { center: Point, radius: Real, omega: Real, phase: Real }, // counter-clockwise for omega > 0
+    Linear { start: Point, velocity: Vector },                         // start + velocity * t
+    Keyframed(Vec<(Real, Point)>), // (time, position) sorted by time, linear in between, held outside
+}
+
+#[derive(Debug, Clone)]
+pub struct Attractor {
+    /*
+    External point mass pulling every particle
+     */
+    pub path: Path,
+    pub gm: Real,   // G * M
+    pub eps2: Real, // softening (prevents insane speed near the attractor)
+    pub kernel: Softening,
+}
+
+impl Attractor {
+    pub fn fixed(position: Point, gm: Real, eps2: Real) -> Self {
+        Attractor { path: Path::Fixed(position), gm, eps2, kernel: Softening::Plummer }
+    }
+
+    pub fn central() -> Self {
+        /*
+        the viewer's central mass at the origin
+         */
+        Attractor::fixed(Point { x: 0.0, y: 0.0 }, 500.0, 25.0)
+    }
+
+    pub fn binary(center: Point, separation: Real, gm: Real, eps2: Real) -> [Attractor; 2] {
+        /*
+        two equal masses on a circular orbit around center, at the Keplerian angular
+        speed omega^2 = (gm + gm) / separation^3
+         */
+        let radius: Real = 0.5 * separation;
+        let omega: Real = (2.0 * gm / separation.powi(3)).sqrt();
+        let pi: Real = std::f64::consts::PI as Real;
+        [0.0, pi].map(|phase| Attractor {
+            path: Path::Circular { center, radius, omega, phase },
+            gm,
+            eps2,
+            kernel: Softening::Plummer,
+        })
+    }
+
+    pub fn position_at(&self, t: Real) -> Point {
+        match &self.path {
+            Path::Fixed(p) => *p,
+            Path::Circular { center, radius, omega, phase } => {
+                let a: Real = omega * t + phase;
+                Point { x: center.x + radius * a.cos(), y: center.y + radius * a.sin() }
+            }
+            Path::Linear { start, velocity } => start.add_vec(&velocity.mult_scalar(t)),
+            Path::Keyframed(keys) => {
+                // first key at or after t, the one before it is the start of the segment
+                let next: usize = keys.partition_point(|k| k.0 < t);
+                if next == 0 {
+                    return keys.first().map_or(Point::zero(), |k| k.1);
+                }
+                if next == keys.len() {
+                    return keys[next - 1].1;
+                }
+                let (t0, p0) = keys[next - 1];
+                let (t1, p1) = keys[next];
+                let f: Real = (t - t0) / (t1 - t0);
+                Point { x: p0.x + (p1.x - p0.x) * f, y: p0.y + (p1.y - p0.y) * f }
+            }
+        }
+    }
+
+    pub fn accel(&self, pos: Point, t: Real) -> Vector {
+        accel_toward_point(pos, self.position_at(t), self.gm, self.eps2, self.kernel)
+    }
+}
+
+impl ForceField for Attractor {
+    fn accel(&self, pos: Point, t: Real) -> Vector {
+        Attractor::accel(self, pos, t)
+    }
+
+    fn potential(&self, pos: Point, t: Real) -> Real {
+        let c: Point = self.position_at(t);
+        let (dx, dy) = (pos.x - c.x, pos.y - c.y);
+        self.gm * self.kernel.potential(dx * dx + dy * dy, self.eps2)
+    }
+}
+
+pub fn accel_from_all(attractors: &[Attractor], pos: Point, t: Real) -> Vector {
+    attractors
+        .iter()
+        .fold(Vector { x: 0.0, y: 0.0 }, |a, att| a + att.accel(pos, t))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Point, b: Point) -> bool {
+        a.distance(&b) < 1e-4
+    }
+
+    #[test]
+    fn test_paths() {
+        let linear: Attractor = Attractor {
+            path: Path::Linear { start: Point::new(1.0, 2.0), velocity: Vector { x: 3.0, y: -1.0 } },
+            gm: 1.0,
+            eps2: 0.0,
+            kernel: Softening::Plummer,
+        };
+        assert!(close(linear.position_at(2.0), Point::new(7.0, 0.0)));
+
+        let circle: Attractor = Attractor {
+            path: Path::Circular { center: Point::new(1.0, 1.0), radius: 2.0, omega: 0.5, phase: 0.0 },
+            gm: 1.0,
+            eps2: 0.0,
+            kernel: Softening::Plummer,
+        };
+        let quarter: Real = std::f64::consts::PI as Real; // omega * t = pi / 2
+        assert!(close(circle.position_at(0.0), Point::new(3.0, 1.0)));
+        assert!(close(circle.position_at(quarter), Point::new(1.0, 3.0)));
+
+        let keys: Attractor = Attractor {
+            path: Path::Keyframed(vec![(1.0, Point::new(0.0, 0.0)), (3.0, Point::new(4.0, 2.0)), (4.0, Point::new(4.0, 0.0))]),
+            gm: 1.0,
+            eps2: 0.0,
+            kernel: Softening::Plummer,
+        };
+        assert!(close(keys.position_at(0.0), Point::new(0.0, 0.0))); // held before the first key
+        assert!(close(keys.position_at(2.0), Point::new(2.0, 1.0)));
+        assert!(close(keys.position_at(3.0), Point::new(4.0, 2.0)));
+        assert!(close(keys.position_at(3.5), Point::new(4.0, 1.0)));
+        assert!(close(keys.position_at(9.0), Point::new(4.0, 0.0))); // held after the last key
+    }
+
+    #[test]
+    fn test_binary_is_a_kepler_orbit() {
+        let [a, b] = Attractor::binary(Point::new(0.0, 0.0), 10.0, 50.0, 0.0);
+        for step in 0..20 {
+            let t: Real = step as Real * 0.37;
+            let (pa, pb) = (a.position_at(t), b.position_at(t));
+            // opposite each other at the separation
+            assert!(close(pa.add(&pb), Point::zero()));
+            assert!((pa.distance(&pb) - 10.0).abs() < 1e-4);
+            // the centripetal acceleration of a is the pull of b
+            let Path::Circular { radius, omega, .. } = a.path else { unreachable!() };
+            let pull: Vector = b.accel(pa, t);
+            let centripetal: Real = omega * omega * radius;
+            assert!((pull.x.hypot(pull.y) - centripetal).abs() < 1e-4 * centripetal);
+        }
+    }
+
+    #[test]
+    fn test_accel_sums_attractors() {
+        let pos: Point = Point::new(3.0, -4.0);
+        let pair: [Attractor; 2] = [Attractor::central(), Attractor::fixed(Point::new(10.0, 0.0), 100.0, 1.0)];
+        let sum: Vector = accel_from_all(&pair, pos, 0.0);
+        let expected: Vector = pair[0].accel(pos, 0.0) + pair[1].accel(pos, 0.0);
+        assert!((sum.x - expected.x).abs() < 1e-6 && (sum.y - expected.y).abs() < 1e-6);
+        let none: Vector = accel_from_all(&[], pos, 0.0);
+        assert_eq!((none.x, none.y), (0.0, 0.0));
+    }
+}
@@ -1,34 +1,172 @@
-use crate::quadtree::{Point, QuadTree};
-use crate::vector::{Particle, Vector};
+use crate::Real;
+use crate::attractor::{Attractor, accel_from_all};
+use crate::collision::{collide_all_pairs, collide_banded};
+use crate::constraint::Constraints;
+use crate::drag::Drag;
+use crate::field::ForceField;
+use crate::obstacle::Obstacles;
+use crate::pair::{NeighborList, PairForce, pair_accels};
+use crate::quadtree::{Node, Point, QuadTree};
+use crate::softening::{Adaptive, Softening};
+use crate::sph::Sph;
+use crate::simd::{GROUP, InteractionList, ParticleSoa, simd_forces};
+use crate::vector::{Kind, Particle, Vector};
+use rayon::prelude::*;
 use std::ops::Add;
+use std::sync::Arc;
 
-fn inter_point_force(p: Point, q: Point, g: f32, eps2: f32) -> Point {
+const DETERMINISTIC_BAND_ROWS: u32 = 4;
+const MAX_SUBSTEPS: u32 = 64; // cap on the drift substeps with ccd, pairs faster than 64 radii a step can still tunnel
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Multipole {
+    /*
+    How far the far-field expansion of an accepted node is carried
+     */
+    Monopole,
+    Quadrupole,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Opening {
+    /*
+    Criterion used to decide whether a node is far enough away to approximate
+     */
+    Geometric,                     // classic d / r < theta, r measured to the center of mass
+    NearestEdge,                   // d / r < theta, r measured to the nearest edge of the cell
+    SalmonWarren { delta: Real },   // absolute acceleration error bound delta
+    Relative { alpha: Real },       // Gadget-style, error relative to last step's acceleration
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TreeParams {
+    pub theta: Real,
+    pub g: Real,       // gravitational constant, 0 turns gravity off
+    pub coulomb: Real, // Coulomb constant k, 0 turns electrostatics off
+    pub eps2: Real,
+    pub softening: Softening, // kernel used with eps2 (and the adaptive values)
+    pub adaptive: Option<Adaptive>, // per-particle softening from the local density, recomputed every step
+    pub order: Multipole,
+    pub opening: Opening,
+    pub simd: bool, // evaluate interactions with the batched kernels in simd::
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Execution {
+    /*
+    Serial runs everything on the calling thread (all pairs collision check).
+    Parallel runs the force loop and a grid based collision phase on the rayon pool. With
+    deterministic set the collision work is split independent of the thread count, so results
+    are bit-identical no matter how many threads run; otherwise it is split per thread
+     */
+    Serial,
+    Parallel { deterministic: bool },
+}
+
+#[derive(Debug, Clone)]
+pub struct SimParams {
     /*
+    Everything the step applies besides tree gravity, and the neighbor list it keeps between
+    steps. The defaults are the viewer's scene
+     */
+    pub attractors: Vec<Attractor>, // external point masses pulling every particle, empty for none
+    pub fields: Vec<Arc<dyn ForceField>>, // analytic background potentials, empty for none
+    pub collisions: bool,    // resolve particle-particle contacts
+    pub radius: Real,        // particle radius for collisions
+    pub restitution: Real,   // elasticity - how much energy is kept in a collision
+    pub friction: Real,      // Coulomb friction coefficient between touching particles, 0 for frictionless
+    pub ccd: bool,           // drift in substeps of at most a radius, swept against the obstacles, so nothing tunnels
+    pub vmax: Real,          // speed limit, Real::INFINITY for none
+    pub pair: Option<PairForce>, // short-range pair potential, over neighbors if set
+    pub neighbors: Option<NeighborList>, // Verlet list for pair, refreshed by the step when stale
+    pub constraints: Constraints, // springs, rods and pins between particles
+    pub sph: Option<Sph>,         // gas pressure and viscosity between Kind::Gas particles
+    pub obstacles: Obstacles,     // static walls and posts the particles bounce off, empty for none
+    pub drag: Option<Drag>,  // drag toward a background wind
+    pub damping: Option<Real>, // exponential velocity decay rate (1 / time), v *= exp(-damping * dt)
+}
+
+fn default_damping() -> Real {
+    // the viewer's old factor of 0.99 per step at dt = 1/15, exp(-rate / 15) gives 0.99 back exactly
+    -15.0 * (0.99 as Real).ln()
+}
+
+impl Default for SimParams {
+    fn default() -> Self {
+        SimParams {
+            attractors: vec![Attractor::central()],
+            fields: Vec::new(),
+            collisions: true,
+            radius: 1.0,
+            restitution: 0.99,
+            friction: 0.0,
+            ccd: false,
+            vmax: 75.0,
+            pair: None,
+            neighbors: None,
+            constraints: Constraints::default(),
+            sph: None,
+            obstacles: Obstacles::default(),
+            drag: None,
+            damping: Some(default_damping()),
+        }
+    }
+}
+
+impl SimParams {
+    pub fn external_accel(&self, pos: Point, t: Real) -> Vector {
+        /*
+        pull of the attractors and fields on a particle at pos
+         */
+        let a: Vector = accel_from_all(&self.attractors, pos, t);
+        self.fields.iter().fold(a, |a, f| a.add(f.accel(pos, t)))
+    }
+
+    pub fn external_energy(&self, particles: &[Particle], t: Real) -> f64 {
+        /*
+        potential energy of the particles in the attractors and fields, for energy diagnostics
+         */
+        let mut e: f64 = 0.0;
+        for p in particles {
+            let phi: Real = self.attractors.iter().map(|a| a.potential(p.position, t)).sum::<Real>()
+                + self.fields.iter().map(|f| f.potential(p.position, t)).sum::<Real>();
+            e += (p.mass * phi) as f64;
+        }
+        e
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StepStats {
+    pub collisions: u32, // particle-particle contacts, summed over the substeps
+    pub obstacle_collisions: u32, // particle-obstacle contacts
+    pub interactions: u64, // particle-particle and particle-node force evaluations
+}
 
+fn inter_point_force(p: Point, q: Point, m: Real, g: Real, eps2: Real, kernel: Softening) -> Point {
+    /*
+    pull of a particle of mass m at q. Two particles on the same spot without softening pull in
+    no direction (inv_r3 is 0)
     */
-    let dx: f32 = q.x - p.x;
-    let dy: f32 = q.y - p.y;
+    let dx: Real = q.x - p.x;
+    let dy: Real = q.y - p.y;
 
-    let r2: f32 = dx * dx + dy * dy + eps2;
-    let r: f32 = r2.sqrt();
-    let inv_r3: f32 = 1.0 / (r2 * r);
+    let inv_r3: Real = kernel.inv_r3(dx * dx + dy * dy, eps2);
 
     Point {
-        x: g * dx * inv_r3,
-        y: g * dy * inv_r3,
+        x: g * m * dx * inv_r3,
+        y: g * m * dy * inv_r3,
     }
 }
 
-fn force_point_to_mass(p: Point, cm: Point, mass: f32, g: f32, eps2: f32) -> Point {
+fn force_point_to_mass(p: Point, cm: Point, mass: Real, g: Real, eps2: Real, kernel: Softening) -> Point {
     /*
 
     */
-    let dx: f32 = cm.x - p.x;
-    let dy: f32 = cm.y - p.y;
+    let dx: Real = cm.x - p.x;
+    let dy: Real = cm.y - p.y;
 
-    let radius2: f32 = dx * dx + dy * dy + eps2;
-    let radius: f32 = radius2.sqrt();
-    let inv_r3: f32 = 1.0 / (radius2 * radius);
+    let inv_r3: Real = kernel.inv_r3(dx * dx + dy * dy, eps2);
 
     Point {
         x: g * mass * dx * inv_r3,
@@ -36,13 +174,34 @@ fn force_point_to_mass(p: Point, cm: Point, mass: f32, g: f32, eps2: f32) -> Poi
     }
 }
 
-pub fn accel_toward_point(pos: Point, center: Point, gm: f32, eps2: f32) -> Vector {
-    let dx: f32 = center.x - pos.x;
-    let dy: f32 = center.y - pos.y;
+fn quadrupole_correction(p: Point, cm: Point, quad: [Real; 3], g: Real, eps2: Real) -> Point {
+    /*
+    Extra far-field term from the node's quadrupole tensor, added on top of the monopole.
+    With d = cm - p: a = G * (-Q d / r^5 + 5/2 (d.Q.d) d / r^7)
+    */
+    let dx: Real = cm.x - p.x;
+    let dy: Real = cm.y - p.y;
+
+    let radius2: Real = dx * dx + dy * dy + eps2;
+    let radius: Real = radius2.sqrt();
+    let inv_r5: Real = 1.0 / (radius2 * radius2 * radius);
+    let inv_r7: Real = inv_r5 / radius2;
+
+    let qdx: Real = quad[0] * dx + quad[1] * dy;
+    let qdy: Real = quad[1] * dx + quad[2] * dy;
+    let dqd: Real = dx * qdx + dy * qdy;
+
+    Point {
+        x: g * (-qdx * inv_r5 + 2.5 * dqd * dx * inv_r7),
+        y: g * (-qdy * inv_r5 + 2.5 * dqd * dy * inv_r7),
+    }
+}
+
+pub fn accel_toward_point(pos: Point, center: Point, gm: Real, eps2: Real, kernel: Softening) -> Vector {
+    let dx: Real = center.x - pos.x;
+    let dy: Real = center.y - pos.y;
 
-    let r2: f32 = dx * dx + dy * dy + eps2;
-    let r: f32 = r2.sqrt();
-    let inv_r3: f32 = 1.0 / (r2 * r);
+    let inv_r3: Real = kernel.inv_r3(dx * dx + dy * dy, eps2);
 
     // acceleration = GM * r_vec / |r|^3
     Vector {
@@ -51,203 +210,823 @@ pub fn accel_toward_point(pos: Point, center: Point, gm: f32, eps2: f32) -> Vect
     }
 }
 
-pub fn tree_force(p: Point, node: &QuadTree, theta: f32, g: f32, eps2: f32) -> Point {
-    let has_children: bool = node.zones.iter().any(|z| z.is_some());
+pub(crate) fn accept_node(p: Point, node: &Node, params: &TreeParams, a_old: Real) -> bool {
+    /*
+    Multipole acceptance criterion: true if the node can be treated as one body
+    */
+    if node.area.contains(&p) {
+        return false;
+    }
+
+    let size: Point = node.area.size();
+    let d: Real = size.x.max(size.y);
+
+    let dx: Real = node.cm.x - p.x;
+    let dy: Real = node.cm.y - p.y;
+    let r2: Real = dx * dx + dy * dy + params.eps2;
+    let r: Real = r2.sqrt();
 
-    if !has_children {
-        let mut force: Point = Point::zero();
-        for &q in &node.elements {
-            if q.x == p.x && q.y == p.y {
-                continue;
+    match params.opening {
+        Opening::Geometric => (d / r) < params.theta,
+        Opening::NearestEdge => {
+            let r_edge: Real = node.area.distance_to_point(&p).sqrt();
+            d < params.theta * r_edge
+        }
+        Opening::SalmonWarren { delta } => {
+            // b_max: furthest a member of the cell can be from the cm
+            let bx: Real = (node.cm.x - node.area.p1.x).max(node.area.p2.x - node.cm.x);
+            let by: Real = (node.cm.y - node.area.p1.y).max(node.area.p2.y - node.cm.y);
+            let b_max: Real = (bx * bx + by * by).sqrt();
+            // trace of the quadrupole is the second moment sum m |s|^2
+            let b2: Real = node.quad[0] + node.quad[2];
+            let r_crit: Real =
+                0.5 * b_max + (0.25 * b_max * b_max + (3.0 * params.g * b2 / delta).sqrt()).sqrt();
+            r > r_crit
+        }
+        Opening::Relative { alpha } => {
+            if a_old == 0.0 {
+                // no previous acceleration yet (first step), use the geometric test
+                return (d / r) < params.theta;
             }
-            force = force.add(&inter_point_force(p, q, g, eps2));
+            // G M / r^2 * (d / r)^2 <= alpha * |a_old|
+            params.g * node.mass * d * d <= alpha * a_old * r2 * r2
         }
-        return force;
     }
+}
 
-    if node.mass == 0.0 {
-        return Point::zero();
-    }
+pub fn tree_force(
+    p: Point,
+    skip: Option<u32>,
+    tree: &QuadTree,
+    soft: &[Real],
+    params: &TreeParams,
+    a_old: Real,
+    interactions: &mut u32,
+) -> Point {
+    /*
+    Walks the tree in its depth-first order: a leaf or an accepted node jumps to node.next
+    (skipping its subtree), an opened node moves on to its first child at i + 1.
+    skip is the index of the particle at p, which is left out of the sum (None for a point
+    that isn't a particle in the tree).
+    a_old is the magnitude of the particle's acceleration from the previous step,
+    it is only used by the relative opening criterion.
+    soft holds the adaptive softening eps^2 of every particle by index, empty for params.eps2
+    */
+    let g: Real = params.g;
+    let kernel: Softening = params.softening;
+    let me: u32 = skip.unwrap_or(u32::MAX); // no particle has this index
+    // the particle's own softening, pairs take the larger of both
+    let eps2: Real = match skip {
+        Some(i) if !soft.is_empty() => soft[i as usize],
+        _ => params.eps2,
+    };
+    let quad_eps2: Real = if kernel == Softening::Plummer { eps2 } else { 0.0 }; // compact kernels are Newtonian far away
+    let mut force: Point = Point::zero();
 
-    let size: Point = node.area.size();
-    let d: f32 = size.x.max(size.y);
+    let mut i: usize = 0;
+    while i < tree.nodes.len() {
+        let node: &Node = &tree.nodes[i];
 
-    let dx: f32 = node.cm.x - p.x;
-    let dy: f32 = node.cm.y - p.y;
-    let r2: f32 = dx * dx + dy * dy + eps2;
-    let r: f32 = r2.sqrt();
+        if node.is_leaf() {
+            for k in node.start as usize..node.end as usize {
+                if tree.index[k] == me {
+                    continue;
+                }
+                let q: Point = Point { x: tree.xs[k], y: tree.ys[k] };
+                let pair_eps2: Real = if soft.is_empty() { eps2 } else { eps2.max(soft[tree.index[k] as usize]) };
+                force = force.add(&inter_point_force(p, q, tree.masses[k], g, pair_eps2, kernel));
+                *interactions += 1;
+            }
+            i = node.next as usize;
+            continue;
+        }
 
-    let contains_p: bool = node.area.contains(&p);
+        if node.mass == 0.0 {
+            i = node.next as usize;
+            continue;
+        }
 
-    if !contains_p && (d / r) < theta {
-        return force_point_to_mass(p, node.cm, node.mass, g, eps2);
-    }
+        if accept_node(p, node, params, a_old) {
+            *interactions += 1;
+            force = force.add(&force_point_to_mass(p, node.cm, node.mass, g, eps2, kernel));
+            if params.order == Multipole::Quadrupole {
+                force = force.add(&quadrupole_correction(p, node.cm, node.quad, g, quad_eps2));
+            }
+            i = node.next as usize;
+            continue;
+        }
 
-    let mut force: Point = Point::zero();
-    for child in node
-        .zones
-        .iter()
-        .filter_map(|z: &Option<Box<QuadTree>>| z.as_deref())
-    {
-        force = force.add(&tree_force(p, child, theta, g, eps2));
+        i += 1;
     }
     force
 }
 
-fn clamp_speed(v: &mut Vector, vmax: f32) -> () {
+fn accept_charge_node(p: Point, node: &Node, params: &TreeParams, a_old: Real) -> bool {
     /*
-    puts a speed limit on
+    accept_node for the Coulomb walk, measured from the node's charge center. The relative
+    criterion bounds the error with the absolute charge, since the total can cancel.
+    Salmon-Warren has no charge version and falls back to the geometric test
      */
-    let s2: f32 = v.x * v.x + v.y * v.y;
-    let vmax2: f32 = vmax * vmax;
-    if s2 > vmax2 {
-        let s: f32 = s2.sqrt();
-        let k: f32 = vmax / s;
-        v.x *= k;
-        v.y *= k;
-    }
-}
-
-fn resolve_collision(a: &mut Particle, b: &mut Particle, radius: f32, e: f32) -> bool {
-    // vector from a to b
-    let dx: f32 = b.position.x - a.position.x;
-    let dy: f32 = b.position.y - a.position.y;
-
-    let dist2: f32 = dx * dx + dy * dy;
-    if dist2 == 0.0 {
+    if node.area.contains(&p) {
         return false;
     }
+    let size: Point = node.area.size();
+    let d: Real = size.x.max(size.y);
+    let dx: Real = node.qcenter.x - p.x;
+    let dy: Real = node.qcenter.y - p.y;
+    let r2: Real = dx * dx + dy * dy + params.eps2;
 
-    let min_dist: f32 = 2.0 * radius;
-    if dist2 > min_dist * min_dist {
-        return false; // not colliding
+    match params.opening {
+        Opening::NearestEdge => d < params.theta * node.area.distance_to_point(&p).sqrt(),
+        Opening::Relative { alpha } if a_old != 0.0 => {
+            params.coulomb.abs() * node.abs_charge * d * d <= alpha * a_old * r2 * r2
+        }
+        _ => d * d < params.theta * params.theta * r2,
     }
+}
 
-    let dist: f32 = dist2.sqrt();
-    let nx: f32 = dx / dist;
-    let ny: f32 = dy / dist;
-
-    // relative velocity along normal
-    let rvx: f32 = b.velocity.x - a.velocity.x;
-    let rvy: f32 = b.velocity.y - a.velocity.y;
-    let vel_n: f32 = rvx * nx + rvy * ny;
+pub fn tree_coulomb(
+    p: Point,
+    skip: Option<u32>,
+    tree: &QuadTree,
+    soft: &[Real],
+    params: &TreeParams,
+    a_old: Real,
+    interactions: &mut u32,
+) -> Point {
+    /*
+    Electric field k * sum q (p - x) / r^3 at p, by the same walk as tree_force over the
+    nodes' charge moments: an accepted node contributes its total charge and its dipole,
+    nodes without charges are skipped. Multiply by q / m for a particle's acceleration
+    */
+    let k: Real = params.coulomb;
+    let kernel: Softening = params.softening;
+    let me: u32 = skip.unwrap_or(u32::MAX);
+    let eps2: Real = match skip {
+        Some(i) if !soft.is_empty() => soft[i as usize],
+        _ => params.eps2,
+    };
+    let dipole_eps2: Real = if kernel == Softening::Plummer { eps2 } else { 0.0 };
+    let mut field: Point = Point::zero();
 
-    // Apply impulse only if moving toward each other
-    if vel_n < 0.0 {
-        let inv_ma: f32 = 1.0 / a.mass;
-        let inv_mb: f32 = 1.0 / b.mass;
+    let mut i: usize = 0;
+    while i < tree.nodes.len() {
+        let node: &Node = &tree.nodes[i];
 
-        let j: f32 = -(1.0 + e) * vel_n / (inv_ma + inv_mb);
+        if node.abs_charge == 0.0 {
+            i = node.next as usize;
+            continue;
+        }
 
-        let imp_x: f32 = j * nx;
-        let imp_y: f32 = j * ny;
+        if node.is_leaf() {
+            for j in node.start as usize..node.end as usize {
+                let q: Real = tree.charges[j];
+                if q == 0.0 || tree.index[j] == me {
+                    continue;
+                }
+                let pair_eps2: Real = if soft.is_empty() { eps2 } else { eps2.max(soft[tree.index[j] as usize]) };
+                let (dx, dy) = (p.x - tree.xs[j], p.y - tree.ys[j]);
+                let e: Real = k * q * kernel.inv_r3(dx * dx + dy * dy, pair_eps2);
+                field.x += e * dx;
+                field.y += e * dy;
+                *interactions += 1;
+            }
+            i = node.next as usize;
+            continue;
+        }
 
-        a.velocity.x -= imp_x * inv_ma;
-        a.velocity.y -= imp_y * inv_ma;
+        if accept_charge_node(p, node, params, a_old) {
+            *interactions += 1;
+            let (dx, dy) = (p.x - node.qcenter.x, p.y - node.qcenter.y);
+            let e: Real = k * node.charge * kernel.inv_r3(dx * dx + dy * dy, eps2);
+            // dipole: k (3 (P.d) d / r^5 - P / r^3)
+            let r2: Real = dx * dx + dy * dy + dipole_eps2;
+            let inv_r3: Real = 1.0 / (r2 * r2.sqrt());
+            let pd: Real = node.dipole.x * dx + node.dipole.y * dy;
+            field.x += e * dx + k * (3.0 * pd * dx / r2 - node.dipole.x) * inv_r3;
+            field.y += e * dy + k * (3.0 * pd * dy / r2 - node.dipole.y) * inv_r3;
+            i = node.next as usize;
+            continue;
+        }
 
-        b.velocity.x += imp_x * inv_mb;
-        b.velocity.y += imp_y * inv_mb;
+        i += 1;
     }
+    field
+}
 
-    // Positional correction (prevents "sinking" + huge impulses next frame)
-    let penetration: f32 = min_dist - dist;
-    let slop: f32 = 0.01;
-    let percent: f32 = 0.8;
-
-    if penetration > slop {
-        let inv_ma: f32 = 1.0 / a.mass;
-        let inv_mb: f32 = 1.0 / b.mass;
-        let inv_sum: f32 = inv_ma + inv_mb;
-
-        let corr: f32 = (penetration - slop) / inv_sum * percent;
-
-        a.position.x -= corr * nx * inv_ma;
-        a.position.y -= corr * ny * inv_ma;
-
-        b.position.x += corr * nx * inv_mb;
-        b.position.y += corr * ny * inv_mb;
+fn clamp_speed(v: &mut Vector, vmax: Real) {
+    /*
+    puts a speed limit on
+     */
+    let s2: Real = v.x * v.x + v.y * v.y;
+    let vmax2: Real = vmax * vmax;
+    if s2 > vmax2 {
+        let s: Real = s2.sqrt();
+        let k: Real = vmax / s;
+        v.x *= k;
+        v.y *= k;
     }
-
-    return true;
 }
 
 pub fn step_barnes_hut(
     particles: &mut [Particle],
-    root: &QuadTree,
-    dt: f32,
-    theta: f32,
-    g: f32,
-    eps2: f32,
-) -> u32 {
+    tree: &QuadTree,
+    t: Real,
+    dt: Real,
+    params: &TreeParams,
+    sim: &mut SimParams,
+    exec: Execution,
+) -> StepStats {
     /*
-    
+    Advances particles from time t to t + dt. tree must have been built from particles as they are now,
+    its leaves refer to them by index. Moving attractors are taken at time t. sim.neighbors is
+    rebuilt from tree when the particles have moved too far since its last build
     */
 
+    // per-particle softening lengths, from the tree as it is now
+    let soft: Vec<Real> = match &params.adaptive {
+        Some(adaptive) => adaptive.lengths(particles, tree),
+        None => Vec::new(),
+    };
+
     // forces
-    let mut forces: Vec<Point> = Vec::with_capacity(particles.len());
-    for part in particles.iter() {
-        forces.push(tree_force(part.position, root, theta, g, eps2));
-    }
+    let force_on = |(i, part): (usize, &Particle)| -> (Point, u32) {
+        let a_old: Real = (part.accel.x * part.accel.x + part.accel.y * part.accel.y).sqrt();
+        let mut count: u32 = 0;
+        let force: Point = tree_force(part.position, Some(i as u32), tree, &soft, params, a_old, &mut count);
+        (force, count)
+    };
+    let results: Vec<(Point, u32)> = if params.g == 0.0 {
+        vec![(Point::zero(), 0); particles.len()]
+    } else if params.simd {
+        // struct of arrays copy of the particles in tree order, so every group is a spatial cluster
+        let mut soa: ParticleSoa = ParticleSoa::new();
+        soa.load(particles, tree);
+        let in_tree_order: Vec<(Point, u32)> = match exec {
+            Execution::Serial => {
+                let mut list: InteractionList = InteractionList::new();
+                let mut out: Vec<(Point, u32)> = Vec::with_capacity(particles.len());
+                simd_forces(&soa.x, &soa.y, &soa.a_old, &soa.id, tree, &soft, params, &mut list, &mut out);
+                out
+            }
+            Execution::Parallel { .. } => {
+                // chunks are a multiple of GROUP so the groups are the same as in the serial loop
+                let chunk: usize = 32 * GROUP;
+                let parts: Vec<Vec<(Point, u32)>> = soa
+                    .x
+                    .par_chunks(chunk)
+                    .zip(soa.y.par_chunks(chunk))
+                    .zip(soa.a_old.par_chunks(chunk))
+                    .zip(soa.id.par_chunks(chunk))
+                    .map_init(InteractionList::new, |list, (((x, y), a), id)| {
+                        let mut out: Vec<(Point, u32)> = Vec::with_capacity(x.len());
+                        simd_forces(x, y, a, id, tree, &soft, params, list, &mut out);
+                        out
+                    })
+                    .collect();
+                parts.into_iter().flatten().collect()
+            }
+        };
+        // scattered back into particle order
+        let mut results: Vec<(Point, u32)> = vec![(Point::zero(), 0); particles.len()];
+        for (&i, r) in soa.id.iter().zip(in_tree_order) {
+            results[i as usize] = r;
+        }
+        results
+    } else {
+        match exec {
+            Execution::Serial => particles.iter().enumerate().map(force_on).collect(),
+            Execution::Parallel { .. } => particles.par_iter().enumerate().map(force_on).collect(),
+        }
+    };
+
+    // electrostatics, only walked for charged particles
+    let field_on = |(i, part): (usize, &Particle)| -> (Point, u32) {
+        if part.charge == 0.0 {
+            return (Point::zero(), 0);
+        }
+        let a_old: Real = (part.accel.x * part.accel.x + part.accel.y * part.accel.y).sqrt();
+        let mut count: u32 = 0;
+        let field: Point = tree_coulomb(part.position, Some(i as u32), tree, &soft, params, a_old, &mut count);
+        (field.mult_scalar(part.charge / part.mass), count)
+    };
+    let electric: Vec<(Point, u32)> = if params.coulomb == 0.0 || tree.root().abs_charge == 0.0 {
+        Vec::new()
+    } else {
+        match exec {
+            Execution::Serial => particles.iter().enumerate().map(field_on).collect(),
+            Execution::Parallel { .. } => particles.par_iter().enumerate().map(field_on).collect(),
+        }
+    };
+
+    // short-range pair forces between neighbors
+    let short: Vec<Vector> = match &sim.pair {
+        Some(pair) => {
+            if let Some(list) = &mut sim.neighbors {
+                list.update(particles, tree);
+            }
+            pair_accels(particles, tree, pair, sim.neighbors.as_ref(), exec != Execution::Serial)
+        }
+        None => Vec::new(),
+    };
+
+    // springs, the rods and pins are solved after the particles have moved
+    let springs: Vec<Vector> = sim.constraints.spring_accels(particles);
 
-    let center: Point = Point { x: 0.0, y: 0.0 };
-    let gm: f32 = 500.0;
-    let eps2_c: f32 = 25.0;
+    // gas pressure and viscosity, with the rate of change of the internal energy
+    let hydro: Vec<(Vector, Real)> = match &sim.sph {
+        Some(sph) => {
+            let parallel: bool = exec != Execution::Serial;
+            let density: Vec<Real> = sph.densities(particles, tree, parallel);
+            sph.accels(particles, tree, &density, parallel)
+        }
+        None => Vec::new(),
+    };
+
+    let interactions: u64 = results.iter().chain(&electric).map(|r| r.1 as u64).sum();
+    let forces = results.into_iter().map(|r| r.0);
 
     // velocity update only
-    for (part, f_point) in particles.iter_mut().zip(forces.into_iter()) {
+    for (i, (part, f_point)) in particles.iter_mut().zip(forces).enumerate() {
+        let f_point: Point = match electric.get(i) {
+            Some(e) => f_point.add(&e.0),
+            None => f_point,
+        };
         let a_bh: crate::vector::Vector = crate::vector::Vector {
             x: f_point.x,
             y: f_point.y,
         };
-        let a_c: crate::vector::Vector = accel_toward_point(part.position, center, gm, eps2_c);
-        let a: crate::vector::Vector = a_bh.add(a_c);
+        let a_bh: crate::vector::Vector = match short.get(i) {
+            Some(&s) => a_bh.add(s),
+            None => a_bh,
+        };
+        let a_bh: crate::vector::Vector = match springs.get(i) {
+            Some(&s) => a_bh.add(s),
+            None => a_bh,
+        };
+        let a_bh: crate::vector::Vector = match hydro.get(i) {
+            Some(&(h, _)) => a_bh.add(h),
+            None => a_bh,
+        };
+        if let (Some(&(_, du)), Kind::Gas { energy }) = (hydro.get(i), &mut part.kind) {
+            *energy = (*energy + du * dt).max(0.0);
+        }
+        let a: crate::vector::Vector = if sim.attractors.is_empty() && sim.fields.is_empty() {
+            a_bh
+        } else {
+            a_bh.add(sim.external_accel(part.position, t))
+        };
 
+        part.accel = a;
         part.velocity = part.velocity.add(a.mult_scalar(dt));
     }
 
-    let mut collision_count: u32 = 0;
-    let radius: f32 = 1.0;
-    let e: f32 = 0.99; // elasticity - how much energy is transfered during collisions
-
-    for i in 0..particles.len() {
-        for j in (i + 1)..particles.len() {
-            let (left, right) = particles.split_at_mut(j);
-            let a: &mut Particle = &mut left[i];
-            let b: &mut Particle = &mut right[0];
-            resolve_collision(a, b, radius, e);
-            if resolve_collision(a, b, radius, e) {
-                collision_count += 1;
+    let radius: Real = sim.radius;
+    let e: Real = sim.restitution;
+    let contacts = |particles: &mut [Particle]| -> (u32, u32) {
+        let collision_count: u32 = match exec {
+            _ if !sim.collisions => 0,
+            Execution::Serial => collide_all_pairs(particles, radius, e, sim.friction),
+            Execution::Parallel { deterministic: true } => {
+                collide_banded(particles, radius, e, sim.friction, DETERMINISTIC_BAND_ROWS)
             }
-        }
-    }
-
-    // clamp & damp
-    let vmax: f32 = 75.0;
-    let damping: f32 = 0.99;
+            Execution::Parallel { deterministic: false } => {
+                // one band per thread and parity, fewer and bigger tasks
+                let (lo, hi) = particles.iter().fold((Real::INFINITY, Real::NEG_INFINITY), |(lo, hi), p| {
+                    (lo.min(p.position.y), hi.max(p.position.y))
+                });
+                let rows: Real = (hi - lo) / (2.0 * radius) + 1.0;
+                let bands: Real = 2.0 * rayon::current_num_threads() as Real;
+                collide_banded(particles, radius, e, sim.friction, ((rows / bands).ceil() as u32).max(1))
+            }
+        };
+        (collision_count, sim.obstacles.collide(particles, radius, exec != Execution::Serial))
+    };
+    let (mut collision_count, mut obstacle_count) = contacts(particles);
 
+    // drag, clamp & damp
+    let decay: Real = sim.damping.map_or(1.0, |rate| (-rate * dt).exp());
     for part in particles.iter_mut() {
-        clamp_speed(&mut part.velocity, vmax);
-        part.velocity = part.velocity.mult_scalar(damping);
+        if let Some(drag) = &sim.drag {
+            part.velocity = drag.apply(part.velocity, part.position, dt);
+        }
+        clamp_speed(&mut part.velocity, sim.vmax);
+        part.velocity = part.velocity.mult_scalar(decay);
+
+        if let Some(spin) = &mut part.spin {
+            spin.omega *= decay;
+            spin.angle += spin.omega * dt;
+        }
+    }
 
-        let dp: Vector = part.velocity.mult_scalar(dt);
-        part.position = part.position.add_vec(&dp);
+    // drift. With ccd it is split into substeps in which no particle moves more than its radius,
+    // with the contacts resolved again between them, so a fast pair meets inside a substep
+    // instead of jumping past each other and every particle stays on the same clock. Each
+    // substep is swept against the obstacles, which are static and can be hit exactly
+    let substeps: u32 = if sim.ccd && sim.collisions {
+        let fastest: Real = particles.iter().fold(0.0, |m: Real, p| m.max(p.velocity.x.hypot(p.velocity.y)));
+        ((fastest * dt / radius).ceil() as u32).clamp(1, MAX_SUBSTEPS)
+    } else {
+        1
+    };
+    let h: Real = dt / substeps as Real;
+    for s in 0..substeps {
+        if s > 0 {
+            let (pairs, obstacles) = contacts(particles);
+            collision_count += pairs;
+            obstacle_count += obstacles;
+        }
+        if sim.ccd {
+            obstacle_count += sim.obstacles.drift(particles, radius, h, exec != Execution::Serial);
+            continue;
+        }
+        for part in particles.iter_mut() {
+            let dp: Vector = part.velocity.mult_scalar(h);
+            part.position = part.position.add_vec(&dp);
+        }
+    }
+    sim.constraints.project(particles, dt);
+    StepStats {
+        collisions: collision_count,
+        obstacle_collisions: obstacle_count,
+        interactions,
     }
-    collision_count
 }
 
 // helpers to draw the sim
 #[derive(Copy, Clone)]
 pub struct Camera {
-    cx: f32,
-    cy: f32,
-    ppu: f32,
+    pub cx: Real,
+    pub cy: Real,
+    pub ppu: Real,
 }
 
-pub fn world_to_screen(x: f32, y: f32, w: i32, h: i32, camera: Camera) -> [i32; 2] {
+pub fn world_to_screen(x: Real, y: Real, w: i32, h: i32, camera: Camera) -> [i32; 2] {
     /*
     create the space for the simulation to happen
      */
-    let sx: i32 = (w as f32 * 0.5 + (x - camera.cx) * camera.ppu).round() as i32;
-    let sy: i32 = (h as f32 * 0.5 - (y - camera.cy) * camera.ppu).round() as i32; // flip Y
+    let sx: i32 = (w as Real * 0.5 + (x - camera.cx) * camera.ppu).round() as i32;
+    let sy: i32 = (h as Real * 0.5 - (y - camera.cy) * camera.ppu).round() as i32; // flip Y
     [sx, sy]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quadtree::{Rectangle, build_tree};
+
+    fn cluster() -> Vec<Particle> {
+        let mut particles: Vec<Particle> = Vec::new();
+        for i in 0..12 {
+            let x: Real = -4.0 + (i % 4) as Real * 2.7;
+            let y: Real = -3.0 + (i / 4) as Real * 2.1 + (i % 3) as Real * 0.3;
+            particles.push(Particle::new(i as u64, Point { x, y }, Vector { x: 0.0, y: 0.0 }, 1.0));
+        }
+        particles
+    }
+
+    #[test]
+    fn test_quadrupole_beats_monopole() {
+        let particles: Vec<Particle> = cluster();
+        let bounds: Rectangle = Rectangle::new(Point::new(-5.0, -5.0), Point::new(5.0, 5.0));
+        let tree: QuadTree = build_tree(&particles, bounds);
+        assert_eq!(tree.root().mass, 12.0);
+
+        let p: Point = Point::new(30.0, 12.0);
+        let mut exact: Point = Point::zero();
+        for part in &particles {
+            exact = exact.add(&inter_point_force(p, part.position, part.mass, 1.0, 0.0, Softening::Plummer));
+        }
+
+        let mut params: TreeParams = TreeParams {
+            theta: 1.0,
+            g: 1.0,
+            coulomb: 0.0,
+            eps2: 0.0,
+            softening: Softening::Plummer,
+            adaptive: None,
+            order: Multipole::Monopole,
+            opening: Opening::Geometric,
+            simd: false,
+        };
+        let mut count: u32 = 0;
+        let mono: Point = tree_force(p, None, &tree, &[], &params, 0.0, &mut count);
+        params.order = Multipole::Quadrupole;
+        let quad: Point = tree_force(p, None, &tree, &[], &params, 0.0, &mut count);
+
+        let err_mono: Real = mono.distance(&exact);
+        let err_quad: Real = quad.distance(&exact);
+        assert!(err_quad < 0.2 * err_mono, "quad {err_quad} mono {err_mono}");
+    }
+
+    #[test]
+    fn test_unequal_masses_match_direct_sum() {
+        // masses from 0.1 to 10: the moments, the leaf sums and the batched path all weigh them
+        let mut particles: Vec<Particle> = cluster();
+        for (i, p) in particles.iter_mut().enumerate() {
+            p.mass = [0.1, 10.0, 1.0, 3.5][i % 4];
+        }
+        let bounds: Rectangle = Rectangle::new(Point::new(-5.0, -5.0), Point::new(5.0, 5.0));
+        let tree: QuadTree = build_tree(&particles, bounds);
+        let total: Real = particles.iter().map(|p| p.mass).sum();
+        let cm_x: Real = particles.iter().map(|p| p.mass * p.position.x).sum::<Real>() / total;
+        assert!((tree.root().mass - total).abs() < 1e-4 && (tree.root().cm.x - cm_x).abs() < 1e-4);
+
+        let direct = |p: Point, skip: Option<usize>| -> Point {
+            let mut f: Point = Point::zero();
+            for (j, q) in particles.iter().enumerate() {
+                if Some(j) != skip {
+                    f = f.add(&inter_point_force(p, q.position, q.mass, 1.0, 0.0, Softening::Plummer));
+                }
+            }
+            f
+        };
+        let mut params: TreeParams = TreeParams {
+            theta: 0.0,
+            g: 1.0,
+            coulomb: 0.0,
+            eps2: 0.0,
+            softening: Softening::Plummer,
+            adaptive: None,
+            order: Multipole::Monopole,
+            opening: Opening::Geometric,
+            simd: false,
+        };
+        let mut count: u32 = 0;
+        for (i, part) in particles.iter().enumerate() {
+            let exact: Point = direct(part.position, Some(i));
+            let scale: Real = (exact.x * exact.x + exact.y * exact.y).sqrt();
+            let walked: Point = tree_force(part.position, Some(i as u32), &tree, &[], &params, 0.0, &mut count);
+            assert!(walked.distance(&exact) < 1e-4 * scale, "particle {i}");
+        }
+        let mut batched: Vec<Particle> = particles.clone();
+        params.simd = true;
+        let mut sim: SimParams =
+            SimParams { attractors: Vec::new(), collisions: false, vmax: Real::INFINITY, damping: None, ..SimParams::default() };
+        step_barnes_hut(&mut batched, &tree, 0.0, 1.0, &params, &mut sim, Execution::Serial);
+        for (i, part) in batched.iter().enumerate() {
+            let exact: Point = direct(particles[i].position, Some(i));
+            let scale: Real = (exact.x * exact.x + exact.y * exact.y).sqrt();
+            assert!((part.accel.x - exact.x).abs() + (part.accel.y - exact.y).abs() < 1e-4 * scale, "batched {i}");
+        }
+
+        // far away the quadrupole of the weighted cluster still beats the monopole
+        let p: Point = Point::new(30.0, 12.0);
+        let exact: Point = direct(p, None);
+        params = TreeParams { theta: 1.0, simd: false, ..params };
+        let mono: Point = tree_force(p, None, &tree, &[], &params, 0.0, &mut count);
+        params.order = Multipole::Quadrupole;
+        let quad: Point = tree_force(p, None, &tree, &[], &params, 0.0, &mut count);
+        assert!(quad.distance(&exact) < 0.2 * mono.distance(&exact));
+    }
+
+    #[test]
+    fn test_opening_criteria_close_to_direct_sum() {
+        let bounds: Rectangle = Rectangle::new(Point::new(-50.0, -50.0), Point::new(50.0, 50.0));
+        let mut particles: Vec<Particle> = Vec::new();
+        for i in 0..400 {
+            // deterministic scatter, denser toward the middle
+            let t: Real = i as Real * 2.399;
+            let r: Real = 48.0 * ((i as Real + 0.5) / 400.0).powi(2);
+            let position: Point = Point::new(r * t.cos(), r * t.sin());
+            particles.push(Particle::new(i as u64, position, Vector { x: 0.0, y: 0.0 }, 1.0));
+        }
+        let tree: QuadTree = build_tree(&particles, bounds);
+
+        let exact_params: TreeParams = TreeParams {
+            theta: 0.0,
+            g: 1.0,
+            coulomb: 0.0,
+            eps2: 1e-3,
+            softening: Softening::Plummer,
+            adaptive: None,
+            order: Multipole::Monopole,
+            opening: Opening::Geometric,
+            simd: false,
+        };
+        let openings: [Opening; 4] = [
+            Opening::Geometric,
+            Opening::NearestEdge,
+            Opening::SalmonWarren { delta: 1e-4 },
+            Opening::Relative { alpha: 0.001 },
+        ];
+
+        for opening in openings {
+            let params: TreeParams = TreeParams { theta: 0.7, opening, ..exact_params };
+            let mut total_err: Real = 0.0;
+            for (i, part) in particles.iter().enumerate() {
+                let me: Option<u32> = Some(i as u32);
+                let mut count: u32 = 0;
+                let exact: Point = tree_force(part.position, me, &tree, &[], &exact_params, 0.0, &mut count);
+                let a_old: Real = (exact.x * exact.x + exact.y * exact.y).sqrt();
+                let approx: Point = tree_force(part.position, me, &tree, &[], &params, a_old, &mut count);
+                total_err += approx.distance(&exact) / a_old;
+            }
+            let mean_err: Real = total_err / particles.len() as Real;
+            assert!(mean_err < 0.03, "{opening:?}: mean relative error {mean_err}");
+        }
+    }
+
+    #[test]
+    fn test_parallel_deterministic_across_thread_counts() {
+        let bounds: Rectangle = Rectangle::new(Point::new(-30.0, -30.0), Point::new(30.0, 30.0));
+        let mut start: Vec<Particle> = Vec::new();
+        for i in 0..600 {
+            // packed spiral so there are plenty of collisions
+            let t: Real = i as Real * 2.399;
+            let r: Real = 25.0 * ((i as Real + 0.5) / 600.0).sqrt();
+            let position: Point = Point::new(r * t.cos(), r * t.sin());
+            start.push(Particle::new(i as u64, position, Vector { x: -t.sin() * 5.0, y: t.cos() * 5.0 }, 1.0));
+        }
+        let params: TreeParams = TreeParams {
+            theta: 0.7,
+            g: 20.0,
+            coulomb: 0.0,
+            eps2: 1e-3,
+            softening: Softening::Plummer,
+            adaptive: None,
+            order: Multipole::Quadrupole,
+            opening: Opening::Geometric,
+            simd: false,
+        };
+
+        let run = |threads: usize| -> (Vec<Particle>, u32) {
+            let pool: rayon::ThreadPool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| {
+                let mut particles: Vec<Particle> = start.clone();
+                let mut tree: QuadTree = QuadTree::new(bounds);
+                tree.parallel = true;
+                let mut sim: SimParams = SimParams::default();
+                let mut collisions: u32 = 0;
+                for k in 0..20 {
+                    tree.rebuild_morton(&particles, bounds);
+                    let exec: Execution = Execution::Parallel { deterministic: true };
+                    let t: Real = k as Real / 15.0;
+                    collisions += step_barnes_hut(&mut particles, &tree, t, 1.0 / 15.0, &params, &mut sim, exec).collisions;
+                }
+                (particles, collisions)
+            })
+        };
+
+        let (reference, collisions) = run(1);
+        assert!(collisions > 0);
+        for threads in [2, 3, 4] {
+            let (particles, c) = run(threads);
+            assert_eq!(c, collisions);
+            for (a, b) in reference.iter().zip(particles.iter()) {
+                assert_eq!(a.position.x.to_bits(), b.position.x.to_bits());
+                assert_eq!(a.position.y.to_bits(), b.position.y.to_bits());
+                assert_eq!(a.velocity.x.to_bits(), b.velocity.x.to_bits());
+                assert_eq!(a.velocity.y.to_bits(), b.velocity.y.to_bits());
+            }
+        }
+    }
+
+    #[test]
+    fn test_coincident_particles_interact() {
+        // particles 0 and 1 share a spot: each must see the other and particle 2, but not itself
+        let mut particles: Vec<Particle> = cluster()[..3].to_vec();
+        particles[1].position = particles[0].position;
+        let bounds: Rectangle = Rectangle::new(Point::new(-5.0, -5.0), Point::new(5.0, 5.0));
+        let tree: QuadTree = build_tree(&particles, bounds);
+        let from_2: Point = inter_point_force(particles[0].position, particles[2].position, 1.0, 1.0, 0.0, Softening::Plummer);
+
+        for simd in [false, true] {
+            let params: TreeParams = TreeParams {
+                theta: 0.0,
+                g: 1.0,
+                coulomb: 0.0,
+                eps2: 0.0, // no softening, so the 0 distance pair must not blow up
+                softening: Softening::Plummer,
+                adaptive: None,
+                order: Multipole::Monopole,
+                opening: Opening::Geometric,
+                simd,
+            };
+            for (i, part) in particles.iter().enumerate().take(2) {
+                let mut count: u32 = 0;
+                let f: Point = tree_force(part.position, Some(i as u32), &tree, &[], &params, 0.0, &mut count);
+                assert_eq!(count, 2);
+                assert_eq!((f.x, f.y), (from_2.x, from_2.y));
+            }
+
+            let mut sim: SimParams = SimParams { attractors: Vec::new(), collisions: false, ..SimParams::default() };
+            let mut moved: Vec<Particle> = particles.clone();
+            let stats: StepStats = step_barnes_hut(&mut moved, &tree, 0.0, 0.1, &params, &mut sim, Execution::Serial);
+            assert_eq!(stats.interactions, 6);
+            assert!(moved.iter().all(|p| p.velocity.x.is_finite() && p.velocity.y.is_finite()));
+        }
+    }
+
+    #[test]
+    fn test_step_samples_attractors_at_t() {
+        // a single particle at rest and an attractor sweeping along x, the kick points where it is at t
+        let bounds: Rectangle = Rectangle::new(Point::new(-10.0, -10.0), Point::new(10.0, 10.0));
+        let start: Vec<Particle> = vec![Particle::new(0, Point::zero(), Vector { x: 0.0, y: 0.0 }, 1.0)];
+        let tree: QuadTree = build_tree(&start, bounds);
+        let params: TreeParams = TreeParams {
+            theta: 0.7,
+            g: 0.0,
+            coulomb: 0.0,
+            eps2: 0.0,
+            softening: Softening::Plummer,
+            adaptive: None,
+            order: Multipole::Monopole,
+            opening: Opening::Geometric,
+            simd: false,
+        };
+        let sweep: Attractor = Attractor {
+            path: crate::attractor::Path::Linear { start: Point::new(-5.0, 1.0), velocity: Vector { x: 1.0, y: 0.0 } },
+            gm: 10.0,
+            eps2: 0.0,
+            kernel: Softening::Plummer,
+        };
+        let mut sim: SimParams = SimParams { attractors: vec![sweep], collisions: false, damping: None, ..SimParams::default() };
+        for (t, sign) in [(0.0, -1.0), (10.0, 1.0)] {
+            let mut particles: Vec<Particle> = start.clone();
+            step_barnes_hut(&mut particles, &tree, t, 0.1, &params, &mut sim, Execution::Serial);
+            assert_eq!(particles[0].accel.x.signum(), sign);
+            assert!(particles[0].accel.y > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_coulomb_matches_direct_sum() {
+        // a neutral plasma: equal numbers of + and - charges, so far nodes are mostly dipoles
+        let bounds: Rectangle = Rectangle::new(Point::new(-50.0, -50.0), Point::new(50.0, 50.0));
+        let mut rng: crate::rng::Rng = crate::rng::Rng::new(7);
+        let particles: Vec<Particle> = (0..600)
+            .map(|i| {
+                let position: Point = Point::new(rng.range(-49.0, 49.0), rng.range(-49.0, 49.0));
+                let charge: Real = if i % 2 == 0 { 1.0 } else { -1.0 };
+                Particle { charge, ..Particle::new(i, position, Vector { x: 0.0, y: 0.0 }, 1.0) }
+            })
+            .collect();
+        let tree: QuadTree = build_tree(&particles, bounds);
+        assert_eq!(tree.root().charge, 0.0);
+        assert_eq!(tree.root().abs_charge, 600.0);
+
+        let exact_params: TreeParams = TreeParams {
+            theta: 0.0,
+            g: 0.0,
+            coulomb: 2.0,
+            eps2: 1e-2,
+            softening: Softening::Plummer,
+            adaptive: None,
+            order: Multipole::Monopole,
+            opening: Opening::Geometric,
+            simd: false,
+        };
+        let params: TreeParams = TreeParams { theta: 0.5, ..exact_params };
+        let (mut err, mut size, mut count): (Real, Real, u32) = (0.0, 0.0, 0);
+        for (i, part) in particles.iter().enumerate().step_by(7) {
+            let mut direct: Point = Point::zero();
+            for (j, other) in particles.iter().enumerate() {
+                if i != j {
+                    let (dx, dy) = (part.position.x - other.position.x, part.position.y - other.position.y);
+                    let e: Real = 2.0 * other.charge * Softening::Plummer.inv_r3(dx * dx + dy * dy, 1e-2);
+                    direct = direct.add(&Point::new(e * dx, e * dy));
+                }
+            }
+            let me: Option<u32> = Some(i as u32);
+            let exact: Point = tree_coulomb(part.position, me, &tree, &[], &exact_params, 0.0, &mut count);
+            assert!(exact.distance(&direct) <= 1e-3 * (1.0 + direct.distance(&Point::zero())));
+            let approx: Point = tree_coulomb(part.position, me, &tree, &[], &params, 0.0, &mut count);
+            err += approx.distance(&direct);
+            size += direct.distance(&Point::zero());
+        }
+        assert!(err < 0.02 * size, "mean relative error {}", err / size);
+    }
+
+    #[test]
+    fn test_like_charges_repel() {
+        let bounds: Rectangle = Rectangle::new(Point::new(-10.0, -10.0), Point::new(10.0, 10.0));
+        let params: TreeParams = TreeParams {
+            theta: 0.7,
+            g: 0.0, // electrostatics only
+            coulomb: 1.0,
+            eps2: 0.0,
+            softening: Softening::Plummer,
+            adaptive: None,
+            order: Multipole::Monopole,
+            opening: Opening::Geometric,
+            simd: false,
+        };
+        let mut sim: SimParams = SimParams { attractors: Vec::new(), collisions: false, damping: None, ..SimParams::default() };
+        for (q, sign) in [(1.0, -1.0), (-1.0, 1.0)] {
+            let mut particles: Vec<Particle> = [(-1.0, 1.0), (1.0, q)]
+                .iter()
+                .enumerate()
+                .map(|(i, &(x, charge))| {
+                    Particle { charge, ..Particle::new(i as u64, Point::new(x, 0.0), Vector { x: 0.0, y: 0.0 }, 2.0) }
+                })
+                .collect();
+            let tree: QuadTree = build_tree(&particles, bounds);
+            let stats: StepStats = step_barnes_hut(&mut particles, &tree, 0.0, 0.1, &params, &mut sim, Execution::Serial);
+            assert_eq!(stats.interactions, 2);
+            // k q q / r^2 / m = 1 / 4 / 2 on the left particle, pointing away for like charges
+            assert!((particles[0].accel.x - sign * 0.125).abs() < 1e-6, "{}", particles[0].accel.x);
+            assert!((particles[0].accel.x + particles[1].accel.x).abs() < 1e-6);
+        }
+    }
+}
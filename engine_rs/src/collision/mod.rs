@@ -0,0 +1,391 @@
+use crate::Real;
+use crate::morton::radix_sort;
+use crate::vector::Particle;
+use rayon::prelude::*;
+
+fn apply_friction(a: &mut Particle, b: &mut Particle, nx: Real, ny: Real, arm: Real, max_impulse: Real) {
+    /*
+    Coulomb friction: a tangential impulse at the contact point (arm from both centers along
+    the normal) that stops the surfaces sliding against each other, capped at mu times the
+    normal impulse. Particles with spin start turning, the others only lose sliding speed
+     */
+    let (tx, ty) = (-ny, nx);
+    let omega_a: Real = a.spin.map_or(0.0, |s| s.omega);
+    let omega_b: Real = b.spin.map_or(0.0, |s| s.omega);
+    let inv_ia: Real = a.spin.map_or(0.0, |s| 1.0 / s.inertia);
+    let inv_ib: Real = b.spin.map_or(0.0, |s| 1.0 / s.inertia);
+    let (inv_ma, inv_mb) = (1.0 / a.mass, 1.0 / b.mass);
+
+    // sliding speed of b's surface past a's at the contact
+    let vt: Real = (b.velocity.x - a.velocity.x) * tx + (b.velocity.y - a.velocity.y) * ty - arm * (omega_a + omega_b);
+    let k: Real = inv_ma + inv_mb + arm * arm * (inv_ia + inv_ib);
+    let jt: Real = (-vt / k).clamp(-max_impulse, max_impulse);
+
+    // jt along t on b, the opposite on a, both turn the same way
+    a.velocity.x -= jt * tx * inv_ma;
+    a.velocity.y -= jt * ty * inv_ma;
+    b.velocity.x += jt * tx * inv_mb;
+    b.velocity.y += jt * ty * inv_mb;
+    if let Some(s) = &mut a.spin {
+        s.omega -= arm * jt * inv_ia;
+    }
+    if let Some(s) = &mut b.spin {
+        s.omega -= arm * jt * inv_ib;
+    }
+}
+
+fn apply_impulse(a: &mut Particle, b: &mut Particle, nx: Real, ny: Real, arm: Real, e: Real, mu: Real) {
+    // relative velocity along normal
+    let rvx: Real = b.velocity.x - a.velocity.x;
+    let rvy: Real = b.velocity.y - a.velocity.y;
+    let vel_n: Real = rvx * nx + rvy * ny;
+
+    // Apply impulse only if moving toward each other
+    if vel_n < 0.0 {
+        let inv_ma: Real = 1.0 / a.mass;
+        let inv_mb: Real = 1.0 / b.mass;
+
+        let j: Real = -(1.0 + e) * vel_n / (inv_ma + inv_mb);
+
+        let imp_x: Real = j * nx;
+        let imp_y: Real = j * ny;
+
+        a.velocity.x -= imp_x * inv_ma;
+        a.velocity.y -= imp_y * inv_ma;
+
+        b.velocity.x += imp_x * inv_mb;
+        b.velocity.y += imp_y * inv_mb;
+
+        if mu > 0.0 {
+            apply_friction(a, b, nx, ny, arm, mu * j);
+        }
+    }
+}
+
+pub fn contact_time(dx: Real, dy: Real, wx: Real, wy: Real, reach: Real, sweep: Real) -> Option<Real> {
+    /*
+    Time in [0, sweep] at which a point at (dx, dy) moving at (wx, wy) first comes within reach
+    of the origin, None if it doesn't. Solves |d + w t| = reach, 0 if it's already within reach and closing
+     */
+    let qa: Real = wx * wx + wy * wy;
+    let qb: Real = dx * wx + dy * wy; // half the linear coefficient, < 0 while closing
+    let qc: Real = dx * dx + dy * dy - reach * reach;
+    if qb >= 0.0 || qa == 0.0 {
+        return None;
+    }
+    let disc: Real = qb * qb - qa * qc;
+    if disc < 0.0 {
+        return None; // it passes by at more than reach
+    }
+    let t: Real = (qc / (-qb + disc.sqrt())).max(0.0); // smaller root, without the cancellation
+    if t > sweep { None } else { Some(t) }
+}
+
+pub fn resolve_collision(a: &mut Particle, b: &mut Particle, radius: Real, e: Real, mu: Real) -> bool {
+    // vector from a to b
+    let dx: Real = b.position.x - a.position.x;
+    let dy: Real = b.position.y - a.position.y;
+
+    let dist2: Real = dx * dx + dy * dy;
+    if dist2 == 0.0 {
+        return false;
+    }
+
+    let min_dist: Real = 2.0 * radius;
+    if dist2 > min_dist * min_dist {
+        return false; // not colliding
+    }
+
+    let dist: Real = dist2.sqrt();
+    let nx: Real = dx / dist;
+    let ny: Real = dy / dist;
+    apply_impulse(a, b, nx, ny, 0.5 * dist, e, mu);
+
+    // Positional correction (prevents "sinking" + huge impulses next frame)
+    let penetration: Real = min_dist - dist;
+    let slop: Real = 0.01;
+    let percent: Real = 0.8;
+
+    if penetration > slop {
+        let inv_ma: Real = 1.0 / a.mass;
+        let inv_mb: Real = 1.0 / b.mass;
+        let inv_sum: Real = inv_ma + inv_mb;
+
+        let corr: Real = (penetration - slop) / inv_sum * percent;
+
+        a.position.x -= corr * nx * inv_ma;
+        a.position.y -= corr * ny * inv_ma;
+
+        b.position.x += corr * nx * inv_mb;
+        b.position.y += corr * ny * inv_mb;
+    }
+
+    true
+}
+
+pub fn collide_all_pairs(particles: &mut [Particle], radius: Real, e: Real, mu: Real) -> u32 {
+    /*
+    checks every pair of particles, O(n^2)
+     */
+    let mut collision_count: u32 = 0;
+    for i in 0..particles.len() {
+        for j in (i + 1)..particles.len() {
+            let (left, right) = particles.split_at_mut(j);
+            let a: &mut Particle = &mut left[i];
+            let b: &mut Particle = &mut right[0];
+            if resolve_collision(a, b, radius, e, mu) {
+                collision_count += 1;
+            }
+        }
+    }
+    collision_count
+}
+
+fn cell_row(key: u64) -> u32 {
+    (key >> 32) as u32
+}
+
+fn cell_col(key: u64) -> u32 {
+    key as u32
+}
+
+fn collide_band(
+    particles: &mut [Particle],
+    keys: &[u64],
+    own: usize,
+    radius: Real,
+    e: Real,
+    mu: Real,
+) -> u32 {
+    /*
+    Resolves every pair that has its first particle in particles[..own] and touches it
+    (same or neighbouring cell). particles[own..] is the first row of the next band, which is only
+    borrowed for pairs across the band edge. Particles are sorted by (row, col), so every cell is a
+    contiguous run and so are the three cells below one. A particle only visits the rest of its
+    own cell, the cell to its right and the three below
+     */
+    // (key, first index) of every occupied cell
+    let mut cells: Vec<(u64, usize)> = Vec::new();
+    for (i, &key) in keys.iter().enumerate() {
+        if cells.last().is_none_or(|&(k, _)| k != key) {
+            cells.push((key, i));
+        }
+    }
+    let start_of = |c: usize| cells.get(c).map_or(keys.len(), |cell| cell.1);
+
+    let mut collision_count: u32 = 0;
+    // cells[below..below_end] are the cells under the current one, both only move forward
+    let (mut below, mut below_end): (usize, usize) = (0, 0);
+    for (c, &(key, start)) in cells.iter().enumerate() {
+        if start >= own {
+            break;
+        }
+        let (row, col) = (cell_row(key) as u64, cell_col(key) as u64);
+        let right: usize = if cells.get(c + 1).is_some_and(|next| next.0 == key + 1) { c + 2 } else { c + 1 };
+        let (lo, hi) = (((row + 1) << 32) | col.saturating_sub(1), ((row + 1) << 32) | (col + 1));
+        while below < cells.len() && cells[below].0 < lo {
+            below += 1;
+        }
+        below_end = below_end.max(below);
+        while below_end < cells.len() && cells[below_end].0 <= hi {
+            below_end += 1;
+        }
+        for i in start..start_of(c + 1) {
+            for j in (i + 1..start_of(right)).chain(start_of(below)..start_of(below_end)) {
+                let (left, rest) = particles.split_at_mut(j);
+                if resolve_collision(&mut left[i], &mut rest[0], radius, e, mu) {
+                    collision_count += 1;
+                }
+            }
+        }
+    }
+    collision_count
+}
+
+pub fn collide_banded(particles: &mut [Particle], radius: Real, e: Real, mu: Real, band_rows: u32) -> u32 {
+    /*
+    Parallel collision phase. Particles are binned into a grid of cells as wide as a collision
+    (2 * radius), sorted by (row, col), and the rows are grouped into bands of band_rows rows.
+    A band handles every pair starting in it, including pairs with the first row of the next band,
+    so even bands never touch each other's particles and can run at the same time, then the odd bands.
+    The order pairs are resolved in only depends on band_rows, so a fixed band_rows gives the same
+    bits no matter how many threads run it
+     */
+    if particles.is_empty() {
+        return 0;
+    }
+    let cell: Real = 2.0 * radius;
+    let min_x: Real = particles.iter().fold(Real::INFINITY, |m, p| m.min(p.position.x));
+    let min_y: Real = particles.iter().fold(Real::INFINITY, |m, p| m.min(p.position.y));
+
+    let mut keyed: Vec<(u64, u32)> = particles
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let col: u64 = ((p.position.x - min_x) / cell) as u64;
+            let row: u64 = ((p.position.y - min_y) / cell) as u64;
+            ((row << 32) | col, i as u32)
+        })
+        .collect();
+    let mut scratch: Vec<(u64, u32)> = Vec::with_capacity(keyed.len());
+    radix_sort(&mut keyed, &mut scratch); // stable, ties keep index order
+
+    let keys: Vec<u64> = keyed.iter().map(|k| k.0).collect();
+    let mut sorted: Vec<Particle> = keyed.iter().map(|k| particles[k.1 as usize]).collect();
+
+    // first sorted index of every band, and of the first row after it
+    let band_of = |key: u64| cell_row(key) / band_rows;
+    let mut bands: Vec<(usize, usize, usize)> = Vec::new(); // (start, own_end, borrow_end)
+    let mut start: usize = 0;
+    while start < keys.len() {
+        let band: u32 = band_of(keys[start]);
+        let own_end: usize = start + keys[start..].partition_point(|&k| band_of(k) == band);
+        let mut borrow_end: usize = own_end;
+        if own_end < keys.len() && cell_row(keys[own_end]) == (band + 1) * band_rows {
+            let next_row: u32 = cell_row(keys[own_end]);
+            borrow_end = own_end + keys[own_end..].partition_point(|&k| cell_row(k) == next_row);
+        }
+        bands.push((start, own_end, borrow_end));
+        start = own_end;
+    }
+
+    let mut collision_count: u32 = 0;
+    for parity in 0..2 {
+        // carve out disjoint slices for every other band
+        let mut tasks: Vec<(&mut [Particle], &[u64], usize)> = Vec::new();
+        let mut rest: &mut [Particle] = &mut sorted[..];
+        let mut consumed: usize = 0;
+        for &(start, own_end, borrow_end) in bands.iter().skip(parity).step_by(2) {
+            let (_, tail) = rest.split_at_mut(start - consumed);
+            let (slice, tail) = tail.split_at_mut(borrow_end - start);
+            tasks.push((slice, &keys[start..borrow_end], own_end - start));
+            rest = tail;
+            consumed = borrow_end;
+        }
+
+        collision_count += tasks
+            .into_par_iter()
+            .map(|(slice, band_keys, own)| collide_band(slice, band_keys, own, radius, e, mu))
+            .sum::<u32>();
+    }
+
+    for (k, p) in keyed.iter().zip(sorted) {
+        particles[k.1 as usize] = p;
+    }
+    collision_count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quadtree::Point;
+    use crate::vector::{Spin, Vector};
+
+    fn disk(x: Real, y: Real, vx: Real, vy: Real, mass: Real, omega: Real) -> Particle {
+        let spin: Spin = Spin { omega, ..Spin::disk(mass, 1.0) };
+        Particle { spin: Some(spin), ..Particle::new(0, Point::new(x, y), Vector { x: vx, y: vy }, mass) }
+    }
+
+    #[test]
+    fn test_friction_conserves_momentum() {
+        // an oblique hit between spinning disks that just touch
+        let mut a: Particle = disk(0.0, 0.0, 3.0, 1.0, 1.0, 2.0);
+        let mut b: Particle = disk(1.2, 1.6, -1.0, 0.5, 2.5, -1.0);
+        let totals = |a: &Particle, b: &Particle| -> [f64; 4] {
+            let (mut p, mut l, mut ke) = ([0.0; 2], 0.0, 0.0);
+            for q in [a, b] {
+                let s: Spin = q.spin.unwrap();
+                p[0] += (q.mass * q.velocity.x) as f64;
+                p[1] += (q.mass * q.velocity.y) as f64;
+                // about the origin, orbital plus spin
+                l += (q.mass * (q.position.x * q.velocity.y - q.position.y * q.velocity.x) + s.inertia * s.omega) as f64;
+                ke += (0.5 * q.mass * (q.velocity.x.powi(2) + q.velocity.y.powi(2)) + 0.5 * s.inertia * s.omega.powi(2)) as f64;
+            }
+            [p[0], p[1], l, ke]
+        };
+        let before: [f64; 4] = totals(&a, &b);
+        assert!(resolve_collision(&mut a, &mut b, 1.0, 0.5, 0.4));
+        let after: [f64; 4] = totals(&a, &b);
+        for k in 0..3 {
+            assert!((after[k] - before[k]).abs() < 1e-4, "{k}: {} -> {}", before[k], after[k]);
+        }
+        assert!(after[3] < before[3]);
+        assert!(a.spin.unwrap().omega != 2.0 && b.spin.unwrap().omega != -1.0);
+    }
+
+    #[test]
+    fn test_friction_sticks_or_slides() {
+        // a disk bumping up into a much heavier one above it while sliding sideways
+        let hit = |mu: Real| -> Particle {
+            let mut a: Particle = disk(0.0, 0.0, 4.0, 1.0, 1.0, 0.0);
+            let mut b: Particle = disk(0.0, 2.0, 0.0, 0.0, 1e9, 0.0);
+            resolve_collision(&mut a, &mut b, 1.0, 0.0, mu);
+            a
+        };
+        // enough friction: the top of the disk stops sliding, it rolls along b with v_x = r omega
+        let stuck: Particle = hit(10.0);
+        assert!((stuck.velocity.x - stuck.spin.unwrap().omega).abs() < 1e-4);
+        assert!(stuck.velocity.x < 4.0 && stuck.spin.unwrap().omega > 0.0);
+        // little friction: the tangential impulse is capped at mu times the normal one (1)
+        let slid: Particle = hit(0.1);
+        assert!((slid.velocity.x - 3.9).abs() < 1e-4);
+        // none: a normal-only impulse, nothing turns
+        let smooth: Particle = hit(0.0);
+        assert_eq!((smooth.velocity.x, smooth.spin.unwrap().omega), (4.0, 0.0));
+    }
+
+    #[test]
+    fn test_contact_time() {
+        // 8 away closing at 4 comes within 2 after 1.5
+        assert_eq!(contact_time(8.0, 0.0, -4.0, 0.0, 2.0, 2.0), Some(1.5));
+        assert_eq!(contact_time(8.0, 0.0, -4.0, 0.0, 2.0, 1.0), None); // not within the sweep
+        assert_eq!(contact_time(-8.0, 0.0, 4.0, 0.0, 2.0, 2.0), Some(1.5));
+        // already within reach and closing is a contact now
+        assert_eq!(contact_time(1.0, 0.0, -1.0, 0.0, 2.0, 1.0), Some(0.0));
+        // passing 3 off the line never comes within 2, moving away neither
+        assert_eq!(contact_time(8.0, 3.0, -4.0, 0.0, 2.0, 10.0), None);
+        assert_eq!(contact_time(8.0, 0.0, 1.0, 0.0, 2.0, 10.0), None);
+    }
+
+    #[test]
+    fn test_banded_finds_every_touching_pair() {
+        // lattice spacing just under the collision distance, inside the slop so nothing moves
+        let spacing: Real = 1.995;
+        let mut particles: Vec<Particle> = Vec::new();
+        for i in 0..23 {
+            for j in 0..17 {
+                let position: Point = Point::new(0.3 + i as Real * spacing, -7.1 + j as Real * spacing);
+                particles.push(Particle::new((i * 17 + j) as u64, position, Vector { x: 0.0, y: 0.0 }, 1.0));
+            }
+        }
+        // horizontal plus vertical neighbours
+        let expected: u32 = 22 * 17 + 23 * 16;
+
+        for band_rows in [1, 2, 3, 100] {
+            let mut copy: Vec<Particle> = particles.clone();
+            assert_eq!(collide_banded(&mut copy, 1.0, 0.99, 0.0, band_rows), expected);
+        }
+    }
+
+    #[test]
+    fn test_banded_finds_diagonal_pairs() {
+        // a triangular lattice, every other row shifted by half a spacing, so pairs also reach
+        // into the cells diagonally below
+        let spacing: Real = 1.995;
+        let mut particles: Vec<Particle> = Vec::new();
+        for j in 0..15 {
+            for i in 0..20 {
+                let x: Real = 0.7 + (i as Real + 0.5 * (j % 2) as Real) * spacing;
+                let y: Real = -3.2 + j as Real * spacing * (0.75 as Real).sqrt();
+                particles.push(Particle::new((j * 20 + i) as u64, Point::new(x, y), Vector { x: 0.0, y: 0.0 }, 1.0));
+            }
+        }
+        // along the rows plus two between rows for every particle but one per row pair
+        let expected: u32 = 19 * 15 + 14 * (2 * 20 - 1);
+
+        for band_rows in [1, 2, 5] {
+            let mut copy: Vec<Particle> = particles.clone();
+            assert_eq!(collide_banded(&mut copy, 1.0, 0.99, 0.0, band_rows), expected);
+        }
+    }
+}
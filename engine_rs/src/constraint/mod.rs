@@ -0,0 +1,217 @@
+use crate::Real;
+use crate::quadtree::Point;
+use crate::vector::{Particle, Vector};
+use std::collections::HashMap;
+
+const DEFAULT_ITERATIONS: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Spring {
+    /*
+    Hookean spring between the particles with ids a and b, damped along its axis
+     */
+    pub a: u64,
+    pub b: u64,
+    pub rest: Real,      // rest length
+    pub stiffness: Real, // force per unit stretch
+    pub damping: Real,   // force per unit closing speed
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Rod {
+    /*
+    rigid distance constraint, solved on the positions
+     */
+    pub a: u64,
+    pub b: u64,
+    pub length: Real,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Pin {
+    pub particle: u64,
+    pub at: Point, // fixed world point
+}
+
+#[derive(Debug, Clone)]
+pub struct Constraints {
+    /*
+    Springs, rods and pins between particles, referred to by id so they survive reordering.
+    Springs are forces and go into the velocity update with everything else. Rods and pins
+    are position-based: after the particles move, they are pulled back onto the constraints
+    by iterations Gauss-Seidel passes (a rod moves its ends by their inverse masses, a pinned
+    particle counts as infinitely heavy), and the correction is added to the velocities.
+    Constraints naming a missing id are skipped
+     */
+    pub springs: Vec<Spring>,
+    pub rods: Vec<Rod>,
+    pub pins: Vec<Pin>,
+    pub iterations: usize, // projection passes per step, more is stiffer
+}
+
+impl Default for Constraints {
+    fn default() -> Self {
+        Constraints { springs: Vec::new(), rods: Vec::new(), pins: Vec::new(), iterations: DEFAULT_ITERATIONS }
+    }
+}
+
+fn index_by_id(particles: &[Particle]) -> HashMap<u64, usize> {
+    particles.iter().enumerate().map(|(i, p)| (p.id, i)).collect()
+}
+
+impl Constraints {
+    pub fn spring_accels(&self, particles: &[Particle]) -> Vec<Vector> {
+        /*
+        acceleration of every particle from the springs, empty without springs
+         */
+        if self.springs.is_empty() {
+            return Vec::new();
+        }
+        let index: HashMap<u64, usize> = index_by_id(particles);
+        let mut accels: Vec<Vector> = vec![Vector { x: 0.0, y: 0.0 }; particles.len()];
+        for s in &self.springs {
+            let (Some(&i), Some(&j)) = (index.get(&s.a), index.get(&s.b)) else {
+                continue;
+            };
+            let (pa, pb) = (&particles[i], &particles[j]);
+            let dx: Real = pb.position.x - pa.position.x;
+            let dy: Real = pb.position.y - pa.position.y;
+            let len: Real = dx.hypot(dy);
+            if len == 0.0 {
+                continue;
+            }
+            let (nx, ny) = (dx / len, dy / len);
+            // separating speed along the axis
+            let closing: Real = (pb.velocity.x - pa.velocity.x) * nx + (pb.velocity.y - pa.velocity.y) * ny;
+            // pull on a toward b
+            let f: Real = s.stiffness * (len - s.rest) + s.damping * closing;
+            accels[i] = accels[i] + Vector { x: nx, y: ny }.mult_scalar(f / pa.mass);
+            accels[j] = accels[j] + Vector { x: nx, y: ny }.mult_scalar(-f / pb.mass);
+        }
+        accels
+    }
+
+    pub fn project(&self, particles: &mut [Particle], dt: Real) {
+        /*
+        Moves the particles back onto the rods and pins and adds the moves divided by dt to
+        their velocities
+         */
+        if self.rods.is_empty() && self.pins.is_empty() {
+            return;
+        }
+        let index: HashMap<u64, usize> = index_by_id(particles);
+        let before: Vec<Point> = particles.iter().map(|p| p.position).collect();
+        let mut inv_mass: Vec<Real> = particles.iter().map(|p| 1.0 / p.mass).collect();
+        for pin in &self.pins {
+            if let Some(&i) = index.get(&pin.particle) {
+                particles[i].position = pin.at;
+                inv_mass[i] = 0.0;
+            }
+        }
+        let rods: Vec<(usize, usize, Real)> = self
+            .rods
+            .iter()
+            .filter_map(|r| Some((*index.get(&r.a)?, *index.get(&r.b)?, r.length)))
+            .collect();
+        for _ in 0..self.iterations {
+            for &(i, j, length) in &rods {
+                let (wa, wb) = (inv_mass[i], inv_mass[j]);
+                let dx: Real = particles[j].position.x - particles[i].position.x;
+                let dy: Real = particles[j].position.y - particles[i].position.y;
+                let len: Real = dx.hypot(dy);
+                if len == 0.0 || wa + wb == 0.0 {
+                    continue;
+                }
+                // fraction of the offset to close, split by inverse mass
+                let c: Real = (len - length) / (len * (wa + wb));
+                particles[i].position.x += dx * c * wa;
+                particles[i].position.y += dy * c * wa;
+                particles[j].position.x -= dx * c * wb;
+                particles[j].position.y -= dy * c * wb;
+            }
+        }
+        for (p, b) in particles.iter_mut().zip(before) {
+            p.velocity.x += (p.position.x - b.x) / dt;
+            p.velocity.y += (p.position.y - b.y) / dt;
+        }
+    }
+
+    pub fn energy(&self, particles: &[Particle]) -> f64 {
+        /*
+        potential energy stored in the springs, for energy diagnostics
+         */
+        let index: HashMap<u64, usize> = index_by_id(particles);
+        let mut e: f64 = 0.0;
+        for s in &self.springs {
+            if let (Some(&i), Some(&j)) = (index.get(&s.a), index.get(&s.b)) {
+                let stretch: Real = particles[i].position.distance(&particles[j].position) - s.rest;
+                e += (0.5 * s.stiffness * stretch * stretch) as f64;
+            }
+        }
+        e
+    }
+
+    pub fn segments(&self, particles: &[Particle]) -> Vec<(Point, Point)> {
+        /*
+        end points of every spring and rod, for drawing
+         */
+        let index: HashMap<u64, usize> = index_by_id(particles);
+        let pairs = self.springs.iter().map(|s| (s.a, s.b)).chain(self.rods.iter().map(|r| (r.a, r.b)));
+        pairs
+            .filter_map(|(a, b)| Some((particles[*index.get(&a)?].position, particles[*index.get(&b)?].position)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(id: u64, x: Real, y: Real) -> Particle {
+        Particle::new(id, Point::new(x, y), Vector { x: 0.0, y: 0.0 }, 1.0)
+    }
+
+    #[test]
+    fn test_spring_force() {
+        let mut particles: Vec<Particle> = vec![at(7, 0.0, 0.0), at(3, 3.0, 4.0)];
+        particles[1].mass = 2.0;
+        let mut set: Constraints = Constraints::default();
+        set.springs.push(Spring { a: 7, b: 3, rest: 4.0, stiffness: 10.0, damping: 0.0 });
+        // stretched by 1: 10 toward each other, b is twice as heavy
+        let a: Vec<Vector> = set.spring_accels(&particles);
+        assert!((a[0].x - 6.0).abs() < 1e-5 && (a[0].y - 8.0).abs() < 1e-5);
+        assert!((a[1].x + 3.0).abs() < 1e-5 && (a[1].y + 4.0).abs() < 1e-5);
+        assert!((set.energy(&particles) - 5.0).abs() < 1e-5);
+
+        // damping resists separating
+        set.springs[0].rest = 5.0;
+        set.springs[0].damping = 2.0;
+        particles[1].velocity = Vector { x: 3.0, y: 4.0 };
+        let a: Vec<Vector> = set.spring_accels(&particles);
+        assert!((a[0].x - 6.0).abs() < 1e-5 && (a[1].x + 3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_projection_restores_rods_and_pins() {
+        // a bent chain of rods hanging from a pin
+        let mut particles: Vec<Particle> = (0..6).map(|i| at(i, 0.3 * i as Real, -1.4 * i as Real)).collect();
+        let mut set: Constraints = Constraints { iterations: 200, ..Constraints::default() };
+        for i in 0..5 {
+            set.rods.push(Rod { a: i, b: i + 1, length: 1.0 });
+        }
+        set.pins.push(Pin { particle: 0, at: Point::new(0.5, 0.0) });
+        set.rods.push(Rod { a: 5, b: 99, length: 1.0 }); // missing id, skipped
+        particles.reverse(); // order doesn't matter
+        set.project(&mut particles, 0.1);
+
+        let index: HashMap<u64, usize> = index_by_id(&particles);
+        let pos = |id: u64| particles[index[&id]].position;
+        assert!(pos(0).distance(&Point::new(0.5, 0.0)) < 1e-6);
+        for i in 0..5 {
+            assert!((pos(i).distance(&pos(i + 1)) - 1.0).abs() < 1e-3, "rod {i}");
+        }
+        // the pinned particle's velocity carries the move onto the pin
+        assert!((particles[index[&0]].velocity.x - 5.0).abs() < 1e-4);
+        assert_eq!(set.segments(&particles).len(), 5);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::Real;
+use crate::quadtree::Point;
+use crate::vector::Vector;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Wind {
+    /*
+    Background velocity the particles are dragged toward
+     */
+    Still,
+    Uniform(Vector),
+    Rotating { center: Point, omega: Real }, // rigid rotation, counter-clockwise for omega > 0
+}
+
+impl Wind {
+    pub fn velocity_at(&self, pos: Point) -> Vector {
+        match *self {
+            Wind::Still => Vector { x: 0.0, y: 0.0 },
+            Wind::Uniform(w) => w,
+            Wind::Rotating { center, omega } => Vector {
+                x: -omega * (pos.y - center.y),
+                y: omega * (pos.x - center.x),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Drag {
+    /*
+    Drag on the velocity u = v - wind relative to the background:
+    du/dt = -(linear + quadratic * |u|) u
+     */
+    pub linear: Real,    // Stokes drag rate, 1 / time
+    pub quadratic: Real, // quadratic drag coefficient, 1 / length
+    pub wind: Wind,
+}
+
+impl Drag {
+    pub fn apply(&self, v: Vector, pos: Point, dt: Real) -> Vector {
+        /*
+        Advances the relative velocity by dt with the exact solution of the drag equation
+        (its direction doesn't change, only its size), so the result doesn't depend on how
+        a time span is split into steps and a large dt can't overshoot past the wind speed
+         */
+        let w: Vector = self.wind.velocity_at(pos);
+        let (ux, uy) = (v.x - w.x, v.y - w.y);
+        let s: Real = (ux * ux + uy * uy).sqrt();
+        if s == 0.0 {
+            return v;
+        }
+        let (k1, k2) = (self.linear, self.quadratic);
+        let s_new: Real = if k1 > 0.0 {
+            let decay: Real = (-k1 * dt).exp();
+            k1 * s * decay / (k1 + k2 * s * (1.0 - decay))
+        } else {
+            s / (1.0 + k2 * s * dt)
+        };
+        let f: Real = s_new / s;
+        Vector { x: w.x + ux * f, y: w.y + uy * f }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn speed(v: Vector) -> Real {
+        (v.x * v.x + v.y * v.y).sqrt()
+    }
+
+    #[test]
+    fn test_splitting_dt_gives_the_same_velocity() {
+        let v: Vector = Vector { x: 30.0, y: -40.0 };
+        let pos: Point = Point::new(3.0, 1.0);
+        let winds: [Wind; 2] = [Wind::Still, Wind::Uniform(Vector { x: 5.0, y: 2.0 })];
+        for wind in winds {
+            for (linear, quadratic) in [(0.7, 0.0), (0.0, 0.05), (0.7, 0.05)] {
+                let drag: Drag = Drag { linear, quadratic, wind };
+                let once: Vector = drag.apply(v, pos, 0.5);
+                let mut split: Vector = v;
+                for _ in 0..50 {
+                    split = drag.apply(split, pos, 0.01);
+                }
+                assert!(speed(once - split) < 1e-3 * speed(v), "{drag:?}: {once:?} vs {split:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_known_solutions() {
+        let pos: Point = Point::zero();
+        let v: Vector = Vector { x: 10.0, y: 0.0 };
+        // Stokes: exponential decay
+        let stokes: Drag = Drag { linear: 2.0, quadratic: 0.0, wind: Wind::Still };
+        assert!((stokes.apply(v, pos, 0.5).x - 10.0 * Real::exp(-1.0)).abs() < 1e-4);
+        // quadratic: 1 / v grows linearly, 1/10 + 0.1 * 1 = 0.2
+        let quad: Drag = Drag { linear: 0.0, quadratic: 0.1, wind: Wind::Still };
+        assert!((quad.apply(v, pos, 1.0).x - 5.0).abs() < 1e-4);
+        // even a huge step only relaxes to the wind, never past it
+        let wind: Drag = Drag { linear: 1.0, quadratic: 1.0, wind: Wind::Uniform(Vector { x: 3.0, y: 0.0 }) };
+        let settled: Vector = wind.apply(v, pos, 1e6);
+        assert!((settled.x - 3.0).abs() < 1e-4 && settled.y == 0.0);
+    }
+
+    #[test]
+    fn test_rotating_wind() {
+        let wind: Wind = Wind::Rotating { center: Point::new(1.0, 1.0), omega: 0.5 };
+        let w: Vector = wind.velocity_at(Point::new(3.0, 1.0));
+        assert_eq!((w.x, w.y), (0.0, 1.0));
+        let w: Vector = wind.velocity_at(Point::new(1.0, -1.0));
+        assert_eq!((w.x, w.y), (1.0, 0.0));
+    }
+}
@@ -0,0 +1,221 @@
+use crate::Real;
+use crate::quadtree::Point;
+use crate::vector::Vector;
+use std::fmt::Debug;
+
+pub trait ForceField: Debug + Send + Sync {
+    /*
+    Analytic background potential, summed into every particle's acceleration by the step.
+    potential is per unit mass and only used for energy diagnostics; accel must be its
+    negative gradient
+     */
+    fn accel(&self, pos: Point, t: Real) -> Vector;
+    fn potential(&self, pos: Point, t: Real) -> Real;
+}
+
+fn offset(pos: Point, center: Point) -> (Real, Real, Real) {
+    // pos - center and its squared length
+    let dx: Real = pos.x - center.x;
+    let dy: Real = pos.y - center.y;
+    (dx, dy, dx * dx + dy * dy)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Uniform {
+    pub g: Vector, // e.g. (0, -9.81)
+}
+
+impl ForceField for Uniform {
+    fn accel(&self, _pos: Point, _t: Real) -> Vector {
+        self.g
+    }
+
+    fn potential(&self, pos: Point, _t: Real) -> Real {
+        -(self.g.x * pos.x + self.g.y * pos.y)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LogHalo {
+    /*
+    phi = v0^2 / 2 * ln(rc^2 + r^2), flat rotation curve at v0 outside the core radius rc
+     */
+    pub center: Point,
+    pub v0: Real,
+    pub rc: Real,
+}
+
+impl ForceField for LogHalo {
+    fn accel(&self, pos: Point, _t: Real) -> Vector {
+        let (dx, dy, r2) = offset(pos, self.center);
+        let k: Real = -self.v0 * self.v0 / (self.rc * self.rc + r2);
+        Vector { x: k * dx, y: k * dy }
+    }
+
+    fn potential(&self, pos: Point, _t: Real) -> Real {
+        let (_, _, r2) = offset(pos, self.center);
+        0.5 * self.v0 * self.v0 * (self.rc * self.rc + r2).ln()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Nfw {
+    /*
+    Navarro-Frenk-White dark matter halo, phi = -gm * ln(1 + r / rs) / r with
+    gm = 4 pi G rho0 rs^3
+     */
+    pub center: Point,
+    pub gm: Real,
+    pub rs: Real,
+}
+
+impl ForceField for Nfw {
+    fn accel(&self, pos: Point, _t: Real) -> Vector {
+        let (dx, dy, r2) = offset(pos, self.center);
+        if r2 == 0.0 {
+            return Vector { x: 0.0, y: 0.0 };
+        }
+        let r: Real = r2.sqrt();
+        let x: Real = r / self.rs;
+        // mass enclosed within r, in units of gm
+        let enclosed: Real = x.ln_1p() - x / (1.0 + x);
+        let k: Real = -self.gm * enclosed / (r2 * r);
+        Vector { x: k * dx, y: k * dy }
+    }
+
+    fn potential(&self, pos: Point, _t: Real) -> Real {
+        let (_, _, r2) = offset(pos, self.center);
+        if r2 == 0.0 {
+            return -self.gm / self.rs;
+        }
+        let r: Real = r2.sqrt();
+        -self.gm * (r / self.rs).ln_1p() / r
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MiyamotoNagai {
+    /*
+    Miyamoto-Nagai disk seen in its own plane (z = 0), where
+    phi = -gm / sqrt(R^2 + (a + b)^2)
+     */
+    pub center: Point,
+    pub gm: Real,
+    pub a: Real, // radial scale length
+    pub b: Real, // vertical scale height
+}
+
+impl ForceField for MiyamotoNagai {
+    fn accel(&self, pos: Point, _t: Real) -> Vector {
+        let (dx, dy, r2) = offset(pos, self.center);
+        let s2: Real = r2 + (self.a + self.b).powi(2);
+        let k: Real = -self.gm / (s2 * s2.sqrt());
+        Vector { x: k * dx, y: k * dy }
+    }
+
+    fn potential(&self, pos: Point, _t: Real) -> Real {
+        let (_, _, r2) = offset(pos, self.center);
+        -self.gm / (r2 + (self.a + self.b).powi(2)).sqrt()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Harmonic {
+    /*
+    harmonic trap, phi = omega^2 / 2 * r^2
+     */
+    pub center: Point,
+    pub omega: Real,
+}
+
+impl ForceField for Harmonic {
+    fn accel(&self, pos: Point, _t: Real) -> Vector {
+        let (dx, dy, _) = offset(pos, self.center);
+        let k: Real = -self.omega * self.omega;
+        Vector { x: k * dx, y: k * dy }
+    }
+
+    fn potential(&self, pos: Point, _t: Real) -> Real {
+        let (_, _, r2) = offset(pos, self.center);
+        0.5 * self.omega * self.omega * r2
+    }
+}
+
+pub struct FieldFn<A, P> {
+    /*
+    user supplied field from an acceleration and a potential closure
+     */
+    pub accel: A,
+    pub potential: P,
+}
+
+impl<A, P> Debug for FieldFn<A, P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("FieldFn")
+    }
+}
+
+impl<A, P> ForceField for FieldFn<A, P>
+where
+    A: Fn(Point, Real) -> Vector + Send + Sync,
+    P: Fn(Point, Real) -> Real + Send + Sync,
+{
+    fn accel(&self, pos: Point, t: Real) -> Vector {
+        (self.accel)(pos, t)
+    }
+
+    fn potential(&self, pos: Point, t: Real) -> Real {
+        (self.potential)(pos, t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attractor::Attractor;
+
+    fn fields() -> Vec<Box<dyn ForceField>> {
+        let c: Point = Point::new(1.0, -2.0);
+        vec![
+            Box::new(Uniform { g: Vector { x: 0.5, y: -9.81 } }),
+            Box::new(LogHalo { center: c, v0: 3.0, rc: 2.0 }),
+            Box::new(Nfw { center: c, gm: 200.0, rs: 5.0 }),
+            Box::new(MiyamotoNagai { center: c, gm: 300.0, a: 3.0, b: 0.5 }),
+            Box::new(Harmonic { center: c, omega: 0.7 }),
+            Box::new(FieldFn {
+                accel: |p: Point, t: Real| Vector { x: -2.0 * p.x * t, y: 0.0 },
+                potential: |p: Point, t: Real| p.x * p.x * t,
+            }),
+            Box::new(Attractor::fixed(c, 100.0, 4.0)),
+        ]
+    }
+
+    #[test]
+    fn test_accel_is_minus_gradient() {
+        // central differences of the potential in f64 steps big enough for f32
+        let h: Real = 1e-2;
+        for field in fields() {
+            for (x, y) in [(4.0, 3.0), (-6.0, 0.5), (1.5, -1.0), (20.0, -30.0)] {
+                let p: Point = Point::new(x, y);
+                let t: Real = 1.5;
+                let gx: f64 = (field.potential(Point::new(x + h, y), t) as f64 - field.potential(Point::new(x - h, y), t) as f64) / (2.0 * h as f64);
+                let gy: f64 = (field.potential(Point::new(x, y + h), t) as f64 - field.potential(Point::new(x, y - h), t) as f64) / (2.0 * h as f64);
+                let a: Vector = field.accel(p, t);
+                let scale: f64 = 1.0 + (gx * gx + gy * gy).sqrt();
+                assert!(
+                    (a.x as f64 + gx).abs() < 1e-2 * scale && (a.y as f64 + gy).abs() < 1e-2 * scale,
+                    "{field:?} at ({x}, {y}): accel ({}, {}) but -grad ({}, {})",
+                    a.x, a.y, -gx, -gy
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_nfw_center_is_finite() {
+        let nfw: Nfw = Nfw { center: Point::zero(), gm: 200.0, rs: 5.0 };
+        let a: Vector = nfw.accel(Point::zero(), 0.0);
+        assert_eq!((a.x, a.y), (0.0, 0.0));
+        assert_eq!(nfw.potential(Point::zero(), 0.0), -40.0);
+    }
+}
@@ -0,0 +1,26 @@
+// the unit tests compare against f64 references, a no-op cast with the f64 feature
+#![cfg_attr(test, allow(clippy::unnecessary_cast))]
+
+pub mod attractor;
+pub mod barnes_hut;
+pub mod collision;
+pub mod constraint;
+pub mod drag;
+pub mod field;
+pub mod morton;
+pub mod obstacle;
+pub mod pair;
+pub mod quadtree;
+pub mod rng;
+pub mod scenario;
+pub mod simd;
+pub mod snapshot;
+pub mod softening;
+pub mod sph;
+pub mod vector;
+
+// Floating point type used by the whole engine, build with `--features f64` for double precision
+#[cfg(not(feature = "f64"))]
+pub type Real = f32;
+#[cfg(feature = "f64")]
+pub type Real = f64;
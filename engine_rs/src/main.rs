@@ -3,21 +3,31 @@ use beryllium::{
     init::InitFlags,
     video::{CreateWinArgs, RendererFlags},
 };
-use std::fs::OpenOptions;
-use std::io::Write;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Write};
+use std::sync::Arc;
 use std::time::Instant;
 use std::time::{Duration};
-use crate::quadtree::{Point, build_tree, Rectangle};
-use crate::vector::Particle;
-
-mod barnes_hut;
-mod quadtree;
-mod vector;
+use engine_rs::Real;
+use engine_rs::attractor::Attractor;
+use engine_rs::barnes_hut::{Camera, Execution, Multipole, Opening, SimParams, TreeParams, step_barnes_hut, world_to_screen};
+use engine_rs::constraint::Constraints;
+use engine_rs::field::Uniform;
+use engine_rs::morton::sort_particles;
+use engine_rs::obstacle::{Obstacles, read_obstacles};
+use engine_rs::quadtree::{Point, QuadTree, Rectangle};
+use engine_rs::rng::seed_from_args;
+use engine_rs::scenario::{Distribution, cloth, generate_around};
+use engine_rs::softening::Softening;
+use engine_rs::vector::{Particle, Spin, Vector};
 
 
 pub fn main() {
     let sdl: Sdl = Sdl::init(InitFlags::EVERYTHING);
 
+    // `--seed <n>` or SEED=<n> to change the starting positions
+    let seed: u64 = seed_from_args();
+
     // set up the CSV file to log collisions per second
     let mut csv = OpenOptions::new()
         .create(true)
@@ -26,7 +36,8 @@ pub fn main() {
         .open("collisions_per_sec.csv")
         .expect("failed to open csv");
 
-    writeln!(csv, "t_sec,collisions_per_sec").unwrap();
+    writeln!(csv, "# seed={seed}").unwrap();
+    writeln!(csv, "t_sec,collisions_per_sec,interactions_per_particle").unwrap();
 
     let start_time = Instant::now();
 
@@ -54,54 +65,87 @@ pub fn main() {
     };
 
     let n: usize = 1000; // # of particles
-    let mut particles: Vec<Particle> = Vec::with_capacity(n);
 
     let mut coll_accum: u64 = 0;
+    let mut inter_accum: u64 = 0;
+    let mut step_accum: u64 = 0;
     let mut coll_timer = Instant::now();
 
-    // generate random starting positions
-    let mut seed: u32 = 123456789;
-    let mut next_f32 = || {
-        seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
-        (seed as f32) / (u32::MAX as f32)
+    // `--binary` swaps the central mass for two half masses orbiting each other
+    let attractors: Vec<Attractor> = if std::env::args().any(|a| a == "--binary") {
+        Attractor::binary(Point { x: 0.0, y: 0.0 }, 30.0, 250.0, 25.0).to_vec()
+    } else {
+        vec![Attractor::central()]
     };
 
-    for _ in 0..n {
-        let x: f32 = bounds.p1.x + (bounds.p2.x - bounds.p1.x) * next_f32();
-        let y: f32 = bounds.p1.y + (bounds.p2.y - bounds.p1.y) * next_f32();
-        let center: Point = Point { x: 0.0, y: 0.0 };
-        let gm: f32 = 500.0; // Mass of the central point
-        let eps2: f32 = 25.0; // softening (prevents insane speed near center)
-
-        let dx: f32 = x - center.x;
-        let dy: f32 = y - center.y;
-        let r2: f32 = dx * dx + dy * dy + eps2;
-        let r: f32 = r2.sqrt();
-
-        // circular orbit speed
-        let v: f32 = (gm / r).sqrt();
-
-        // perpendicular direction gives orbit
-        let vx: f32 = -dy / r * v;
-        let vy: f32 = dx / r * v;
-
-        particles.push(Particle {
-            position: Point { x, y },
-            velocity: crate::vector::Vector { x: vx, y: vy },
-            mass: 1.0,
-        });
-    }
+    // `--cloth` hangs a sheet held together by rods and springs under uniform gravity instead
+    let cloth_scene: bool = std::env::args().any(|a| a == "--cloth");
+    // `--friction` makes the particles spinning disks with Coulomb friction between them
+    let friction: bool = std::env::args().any(|a| a == "--friction");
+    // `--ccd` substeps the drift so fast particles can't pass through each other or the obstacles
+    let ccd: bool = std::env::args().any(|a| a == "--ccd");
+
+    // random starting positions on circular orbits, reproducible from the seed (or the cloth)
+    let (mut particles, constraints): (Vec<Particle>, Constraints) = if cloth_scene {
+        cloth(Point { x: -45.0, y: 65.0 }, 31, 21, 3.0)
+    } else {
+        (generate_around(Distribution::Uniform, n, bounds, seed, &attractors), Constraints::default())
+    };
 
-    let dt: f32 = 1.0 / 15.0;
-    let theta: f32 = 0.7;
-    let g: f32 = 20.0;
-    let eps2: f32 = 1e-3;
+    let dt: Real = 1.0 / 15.0;
+    let params: TreeParams = TreeParams {
+        theta: 0.7,
+        g: if cloth_scene { 0.0 } else { 20.0 },
+        coulomb: 0.0,
+        eps2: 1e-3,
+        softening: Softening::Plummer,
+        adaptive: None,
+        order: Multipole::Monopole, // Quadrupole allows a larger theta for the same accuracy
+        opening: Opening::Geometric,
+        simd: false,
+    };
+    // attractors, collisions, speed limit and damping
+    let mut sim: SimParams = if cloth_scene {
+        SimParams {
+            attractors: Vec::new(),
+            fields: vec![Arc::new(Uniform { g: Vector { x: 0.0, y: -9.81 } })],
+            collisions: false,
+            constraints,
+            ..SimParams::default()
+        }
+    } else {
+        SimParams { attractors, ccd, ..SimParams::default() }
+    };
+    if friction {
+        sim.friction = 0.5;
+        for p in particles.iter_mut() {
+            p.spin = Some(Spin::disk(p.mass, sim.radius));
+        }
+    }
+    // `--obstacles <file>` loads static walls and posts, see obstacles.txt for the format
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(path) = args.iter().position(|a| a == "--obstacles").and_then(|i| args.get(i + 1)) {
+        let file: File = File::open(path).unwrap_or_else(|e| panic!("can't open {path}: {e}"));
+        let obstacles: Obstacles = read_obstacles(BufReader::new(file)).unwrap_or_else(|e| panic!("{path}: {e}"));
+        // drop the particles that would start inside or on an obstacle
+        let mut probe: Vec<Particle> = particles.clone();
+        obstacles.collide(&mut probe, sim.radius, false);
+        particles = particles.into_iter().zip(&probe).filter(|(p, q)| p.position.distance(&q.position) == 0.0).map(|(p, _)| p).collect();
+        sim.obstacles = obstacles;
+    }
+    // bit-identical results for any number of threads (set RAYON_NUM_THREADS to change it)
+    let exec: Execution = Execution::Parallel { deterministic: true };
 
     let target_frame: Duration = Duration::from_secs_f32(1.0 / 60.0);
     let mut last_frame: Instant = Instant::now();
 
     // Reuse allocations
     let mut rects: Vec<[i32; 4]> = Vec::with_capacity(n);
+    let mut tree: QuadTree = QuadTree::new(bounds);
+    tree.parallel = exec != Execution::Serial;
+    let mut frame: u64 = 0;
+    let mut t: Real = 0.0; // simulation time
+    let resort_every: u64 = 60; // frames between re-sorting the particles into Morton order
 
     'main_loop: loop {
         while let Some((event, _ts)) = sdl.poll_events() {
@@ -119,9 +163,9 @@ pub fn main() {
         let (w, h) = win.get_window_size();
 
         // Compute camera every frame (cheap) so resizing / DPI changes won't break mapping
-        let world_w: f32 = bounds.p2.x - bounds.p1.x;
-        let world_h: f32 = bounds.p2.y - bounds.p1.y;
-        let ppu: f32 = (w as f32 / world_w).min(h as f32 / world_h) * 0.95;
+        let world_w: Real = bounds.p2.x - bounds.p1.x;
+        let world_h: Real = bounds.p2.y - bounds.p1.y;
+        let ppu: Real = (w as Real / world_w).min(h as Real / world_h) * 0.95;
 
         let cam: Camera = Camera {
             cx: (bounds.p1.x + bounds.p2.x) * 0.5,
@@ -129,25 +173,51 @@ pub fn main() {
             ppu,
         };
 
-        let root = build_tree(&particles, bounds);
-        let c = step_barnes_hut(&mut particles, &root, dt, theta, g, eps2);
-        coll_accum += c as u64;
+        if frame.is_multiple_of(resort_every) {
+            sort_particles(&mut particles, bounds);
+        }
+        frame += 1;
+
+        tree.rebuild_morton(&particles, bounds);
+        let stats = step_barnes_hut(&mut particles, &tree, t, dt, &params, &mut sim, exec);
+        t += dt;
+        coll_accum += stats.collisions as u64;
+        inter_accum += stats.interactions;
+        step_accum += 1;
 
         let elapsed = coll_timer.elapsed().as_secs_f32();
         if elapsed >= 1.0 {
             let cps = coll_accum as f32 / elapsed;
             let t = start_time.elapsed().as_secs_f32();
+            // average number of force interactions per particle per step
+            let ipp = inter_accum as f32 / (step_accum as f32 * particles.len() as f32);
 
-            writeln!(csv, "{:.3},{:.3}", t, cps).unwrap();
+            writeln!(csv, "{:.3},{:.3},{:.1}", t, cps, ipp).unwrap();
             csv.flush().unwrap(); // optional, but nice for live logging
 
             coll_accum = 0;
+            inter_accum = 0;
+            step_accum = 0;
             coll_timer = Instant::now();
         }
 
         win.set_draw_color(255, 255, 255, 255).unwrap();
         win.clear().unwrap();
 
+        // springs and rods as gray lines under the particles
+        win.set_draw_color(160, 160, 160, 255).unwrap();
+        for (a, b) in sim.constraints.segments(&particles) {
+            let line: [[i32; 2]; 2] = [world_to_screen(a.x, a.y, w, h, cam), world_to_screen(b.x, b.y, w, h, cam)];
+            win.draw_lines(&line).unwrap();
+        }
+
+        // obstacles as dark green lines
+        win.set_draw_color(0, 120, 60, 255).unwrap();
+        for (a, b) in sim.obstacles.outline() {
+            let line: [[i32; 2]; 2] = [world_to_screen(a.x, a.y, w, h, cam), world_to_screen(b.x, b.y, w, h, cam)];
+            win.draw_lines(&line).unwrap();
+        }
+
         // Draw particles as small filled squares (makes it easier to see)
         rects.clear();
         let r: i32 = 1; // radius -> 3x3
@@ -165,6 +235,33 @@ pub fn main() {
         win.set_draw_color(0, 0, 0, 255).unwrap();
         win.fill_rects(&rects).unwrap();
 
+        // orientation markers from the center of every spinning particle to its edge
+        win.set_draw_color(0, 90, 200, 255).unwrap();
+        for p in &particles {
+            let Some(spin) = p.spin else {
+                continue;
+            };
+            let edge: Point = Point {
+                x: p.position.x + sim.radius * spin.angle.cos(),
+                y: p.position.y + sim.radius * spin.angle.sin(),
+            };
+            let line: [[i32; 2]; 2] = [
+                world_to_screen(p.position.x, p.position.y, w, h, cam),
+                world_to_screen(edge.x, edge.y, w, h, cam),
+            ];
+            win.draw_lines(&line).unwrap();
+        }
+
+        // attractors as bigger red squares
+        rects.clear();
+        for a in &sim.attractors {
+            let pos: Point = a.position_at(t);
+            let [sx, sy] = world_to_screen(pos.x, pos.y, w, h, cam);
+            rects.push([sx - 3, sy - 3, 7, 7]);
+        }
+        win.set_draw_color(200, 0, 0, 255).unwrap();
+        win.fill_rects(&rects).unwrap();
+
         win.present();
 
         // frame limit
@@ -0,0 +1,153 @@
+use crate::quadtree::{Point, Rectangle};
+use crate::vector::Particle;
+
+pub const KEY_BITS: u32 = 32; // bits per axis, one level of the tree per bit
+
+fn quantize(t: f64) -> u32 {
+    /*
+    maps t in [0, 1] onto a 32 bit grid so that the top bit is set only for t > 0.5,
+    which keeps points on a split line on the north / west side like the tree does
+     */
+    let scaled: f64 = (t * 4294967296.0).ceil() - 1.0;
+    scaled.clamp(0.0, u32::MAX as f64) as u32
+}
+
+fn spread(v: u32) -> u64 {
+    // moves bit i of v to bit 2i
+    let mut x: u64 = v as u64;
+    x = (x | (x << 16)) & 0x0000_FFFF_0000_FFFF;
+    x = (x | (x << 8)) & 0x00FF_00FF_00FF_00FF;
+    x = (x | (x << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+    x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    x = (x | (x << 1)) & 0x5555_5555_5555_5555;
+    x
+}
+
+#[allow(clippy::useless_conversion)] // Real is already f64 with the f64 feature
+pub fn morton_key(p: &Point, bounds: &Rectangle) -> u64 {
+    /*
+    Z-curve key of p inside bounds. Each 2 bit digit (from the top) is the quadrant
+    index at that depth: (south << 1) | east, which is the NW, NE, SW, SE order of QuadTree::zones
+     */
+    let w: f64 = f64::from(bounds.p2.x - bounds.p1.x);
+    let h: f64 = f64::from(bounds.p2.y - bounds.p1.y);
+    // subtract in f64, a rounded f32 difference can push a point across a split line
+    let east: u32 = quantize((f64::from(p.x) - f64::from(bounds.p1.x)) / w);
+    let south: u32 = quantize((f64::from(bounds.p2.y) - f64::from(p.y)) / h);
+    (spread(south) << 1) | spread(east)
+}
+
+pub fn quadrant_at(key: u64, depth: u32) -> u64 {
+    // the 2 bit quadrant digit of key at the given depth
+    (key >> (2 * (KEY_BITS - 1 - depth))) & 3
+}
+
+pub fn radix_sort<T: Copy>(items: &mut Vec<(u64, T)>, scratch: &mut Vec<(u64, T)>) {
+    /*
+    LSD radix sort on the key, 8 bits per pass. Stable, and passes where every key
+    has the same digit are skipped
+     */
+    for pass in 0..8 {
+        let shift: u32 = pass * 8;
+        let mut counts: [usize; 256] = [0; 256];
+        for (key, _) in items.iter() {
+            counts[((key >> shift) & 0xFF) as usize] += 1;
+        }
+        if counts.contains(&items.len()) {
+            continue;
+        }
+
+        let mut offsets: [usize; 256] = [0; 256];
+        let mut total: usize = 0;
+        for (offset, count) in offsets.iter_mut().zip(counts.iter()) {
+            *offset = total;
+            total += count;
+        }
+
+        scratch.clear();
+        scratch.resize(items.len(), items[0]);
+        for item in items.iter() {
+            let digit: usize = ((item.0 >> shift) & 0xFF) as usize;
+            scratch[offsets[digit]] = *item;
+            offsets[digit] += 1;
+        }
+        std::mem::swap(items, scratch);
+    }
+}
+
+pub fn sort_particles(particles: &mut [Particle], bounds: Rectangle) {
+    /*
+    Reorders the particle array along the Z-curve so that particles close in space
+    are close in memory, which helps the force and collision loops
+     */
+    let mut keyed: Vec<(u64, Particle)> = particles
+        .iter()
+        .map(|p| (morton_key(&p.position, &bounds), *p))
+        .collect();
+    let mut scratch: Vec<(u64, Particle)> = Vec::with_capacity(keyed.len());
+    radix_sort(&mut keyed, &mut scratch);
+
+    for (dst, (_, p)) in particles.iter_mut().zip(keyed) {
+        *dst = p;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Real;
+    use crate::vector::Vector;
+
+    #[test]
+    fn test_key_quadrants() {
+        let bounds: Rectangle = Rectangle::new(Point::new(-10.0, -10.0), Point::new(10.0, 10.0));
+        let nw: u64 = morton_key(&Point::new(-5.0, 5.0), &bounds);
+        let ne: u64 = morton_key(&Point::new(5.0, 5.0), &bounds);
+        let sw: u64 = morton_key(&Point::new(-5.0, -5.0), &bounds);
+        let se: u64 = morton_key(&Point::new(5.0, -5.0), &bounds);
+
+        assert_eq!(quadrant_at(nw, 0), 0);
+        assert_eq!(quadrant_at(ne, 0), 1);
+        assert_eq!(quadrant_at(sw, 0), 2);
+        assert_eq!(quadrant_at(se, 0), 3);
+        // on the split lines: north / west wins
+        assert_eq!(quadrant_at(morton_key(&Point::new(0.0, 0.0), &bounds), 0), 0);
+    }
+
+    #[test]
+    fn test_radix_sort() {
+        let mut seed: u64 = 42;
+        let mut items: Vec<(u64, u32)> = (0..1000)
+            .map(|i| {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                (seed, i)
+            })
+            .collect();
+        let mut expected: Vec<(u64, u32)> = items.clone();
+        expected.sort_by_key(|item| item.0);
+
+        let mut scratch: Vec<(u64, u32)> = Vec::new();
+        radix_sort(&mut items, &mut scratch);
+        assert_eq!(items, expected);
+    }
+
+    #[test]
+    fn test_sort_keeps_ids() {
+        // sorting moves whole particles, so each id and tag stays with its position
+        let bounds: Rectangle = Rectangle::new(Point::new(-10.0, -10.0), Point::new(10.0, 10.0));
+        let mut particles: Vec<Particle> = (0..100u64)
+            .map(|i| {
+                let position: Point = Point::new((i * 37 % 20) as Real - 9.5, (i * 11 % 20) as Real - 9.5);
+                Particle { tag: Some(i as u32 % 3), ..Particle::new(i, position, Vector { x: 0.0, y: 0.0 }, 1.0) }
+            })
+            .collect();
+        let before: Vec<Particle> = particles.clone();
+        sort_particles(&mut particles, bounds);
+
+        assert_ne!(particles.iter().map(|p| p.id).collect::<Vec<u64>>(), (0..100).collect::<Vec<u64>>());
+        for p in &particles {
+            let orig: &Particle = &before[p.id as usize];
+            assert_eq!((p.position.x, p.position.y, p.tag), (orig.position.x, orig.position.y, orig.tag));
+        }
+    }
+}
@@ -0,0 +1,588 @@
+use crate::Real;
+use crate::collision::contact_time;
+use crate::quadtree::Point;
+use crate::vector::{Particle, Vector};
+use rayon::prelude::*;
+use std::io::{self, BufRead};
+
+const DEFAULT_CELL: Real = 10.0;
+const CIRCLE_SIDES: usize = 32; // segments per circle outline in the viewer
+const MAX_CELLS: Real = 1048576.0; // broad-phase grid cells, the cells are widened to stay under it
+const MAX_BOUNCES: u32 = 8; // swept bounces per particle and drift, it stops at the next contact after that
+
+#[derive(Debug, Clone)]
+pub enum Shape {
+    /*
+    Static solid geometry. A polygon is closed and filled: a particle inside it is pushed
+    out through the nearest edge
+     */
+    Segment { a: Point, b: Point },
+    Polygon { corners: Vec<Point> }, // at least 3, in either winding
+    Circle { center: Point, radius: Real },
+}
+
+fn closest_on_segment(a: Point, b: Point, p: Point) -> Point {
+    let (ex, ey) = (b.x - a.x, b.y - a.y);
+    let len2: Real = ex * ex + ey * ey;
+    let t: Real = if len2 == 0.0 { 0.0 } else { (((p.x - a.x) * ex + (p.y - a.y) * ey) / len2).clamp(0.0, 1.0) };
+    Point { x: a.x + ex * t, y: a.y + ey * t }
+}
+
+fn edges(corners: &[Point]) -> impl Iterator<Item = (Point, Point)> + '_ {
+    (0..corners.len()).map(|i| (corners[i], corners[(i + 1) % corners.len()]))
+}
+
+fn segment_time(a: Point, b: Point, p: Point, v: Vector, radius: Real, sweep: Real) -> Option<Real> {
+    /*
+    First time in [0, sweep] a disk at p moving at v touches the segment: a face of the
+    capsule around the segment, or one of its round ends
+     */
+    let (ex, ey) = (b.x - a.x, b.y - a.y);
+    let len: Real = ex.hypot(ey);
+    let end = |c: Point| contact_time(p.x - c.x, p.y - c.y, v.x, v.y, radius, sweep);
+    let ends: Option<Real> = match (end(a), end(b)) {
+        (Some(s), Some(t)) => Some(s.min(t)),
+        (s, t) => s.or(t),
+    };
+    if len == 0.0 {
+        return ends;
+    }
+    let (nx, ny) = (-ey / len, ex / len);
+    let s0: Real = (p.x - a.x) * nx + (p.y - a.y) * ny; // signed distance from the line
+    let vn: Real = v.x * nx + v.y * ny;
+    if s0 * vn >= 0.0 {
+        return ends; // along or away from the line
+    }
+    let t: Real = ((s0.abs() - radius) / vn.abs()).max(0.0);
+    let along: Real = ((p.x + v.x * t - a.x) * ex + (p.y + v.y * t - a.y) * ey) / len;
+    if t <= sweep && (0.0..=len).contains(&along) { Some(t) } else { ends }
+}
+
+impl Shape {
+    fn time_of_impact(&self, p: Point, v: Vector, radius: Real, sweep: Real) -> Option<Real> {
+        // first time in [0, sweep] a disk at p moving at v touches the outline, from outside
+        match self {
+            Shape::Segment { a, b } => segment_time(*a, *b, p, v, radius, sweep),
+            Shape::Polygon { corners } => edges(corners)
+                .filter_map(|(a, b)| segment_time(a, b, p, v, radius, sweep))
+                .min_by(|s, t| s.total_cmp(t)),
+            Shape::Circle { center, radius: r } => contact_time(p.x - center.x, p.y - center.y, v.x, v.y, r + radius, sweep),
+        }
+    }
+
+    fn closest(&self, p: Point) -> Point {
+        // closest point of the shape's outline to p
+        match self {
+            Shape::Segment { a, b } => closest_on_segment(*a, *b, p),
+            Shape::Polygon { corners } => edges(corners)
+                .map(|(a, b)| closest_on_segment(a, b, p))
+                .min_by(|q, r| q.distance(&p).total_cmp(&r.distance(&p)))
+                .unwrap_or(p),
+            Shape::Circle { center, radius } => {
+                let (dx, dy) = (p.x - center.x, p.y - center.y);
+                let d: Real = dx.hypot(dy);
+                if d == 0.0 {
+                    return Point { x: center.x + radius, y: center.y };
+                }
+                Point { x: center.x + dx / d * radius, y: center.y + dy / d * radius }
+            }
+        }
+    }
+
+    fn inside(&self, p: Point) -> bool {
+        // strictly inside a solid shape, by the even-odd rule for polygons
+        match self {
+            Shape::Segment { .. } => false,
+            Shape::Polygon { corners } => edges(corners).fold(false, |inside, (a, b)| {
+                let crosses: bool = (a.y > p.y) != (b.y > p.y) && p.x < a.x + (p.y - a.y) / (b.y - a.y) * (b.x - a.x);
+                inside != crosses
+            }),
+            Shape::Circle { center, radius } => p.distance(center) < *radius,
+        }
+    }
+
+    fn bounds(&self) -> (Point, Point) {
+        match self {
+            Shape::Segment { a, b } => (Point { x: a.x.min(b.x), y: a.y.min(b.y) }, Point { x: a.x.max(b.x), y: a.y.max(b.y) }),
+            Shape::Polygon { corners } => corners.iter().fold(
+                (Point { x: Real::INFINITY, y: Real::INFINITY }, Point { x: Real::NEG_INFINITY, y: Real::NEG_INFINITY }),
+                |(lo, hi), c| (Point { x: lo.x.min(c.x), y: lo.y.min(c.y) }, Point { x: hi.x.max(c.x), y: hi.y.max(c.y) }),
+            ),
+            Shape::Circle { center, radius } => (
+                Point { x: center.x - radius, y: center.y - radius },
+                Point { x: center.x + radius, y: center.y + radius },
+            ),
+        }
+    }
+
+    fn outward(&self, p: Point) -> (Real, Real) {
+        // a normal for a particle sitting exactly on the outline
+        let normal = |a: Point, b: Point| {
+            let (ex, ey) = (b.x - a.x, b.y - a.y);
+            let len: Real = ex.hypot(ey).max(Real::MIN_POSITIVE);
+            (-ey / len, ex / len)
+        };
+        match self {
+            Shape::Segment { a, b } => normal(*a, *b),
+            Shape::Polygon { corners } => {
+                // the left normal of the nearest edge points out of a clockwise polygon
+                let (a, b) = edges(corners)
+                    .min_by(|e, f| closest_on_segment(e.0, e.1, p).distance(&p).total_cmp(&closest_on_segment(f.0, f.1, p).distance(&p)))
+                    .unwrap_or((p, p));
+                let area2: Real = edges(corners).map(|(a, b)| a.x * b.y - b.x * a.y).sum();
+                let (nx, ny) = normal(a, b);
+                if area2 > 0.0 { (-nx, -ny) } else { (nx, ny) }
+            }
+            Shape::Circle { center, .. } => {
+                let (dx, dy) = (p.x - center.x, p.y - center.y);
+                let d: Real = dx.hypot(dy);
+                if d == 0.0 { (1.0, 0.0) } else { (dx / d, dy / d) }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Obstacles {
+    /*
+    Static shapes the particles bounce off. The shapes are binned once into a uniform grid
+    (every cell lists the shapes passing within half a cell diagonal of its center), so a
+    particle only tests the shapes in the cells its disk overlaps instead of all of them
+     */
+    pub shapes: Vec<Shape>,
+    pub restitution: Real,
+    pub friction: Real, // Coulomb friction coefficient
+    cell: Real,
+    origin: Point,
+    cols: usize,
+    rows: usize,
+    starts: Vec<u32>, // shapes of cell c are items[starts[c]..starts[c + 1]]
+    items: Vec<u32>,
+}
+
+impl Default for Obstacles {
+    fn default() -> Self {
+        Obstacles::new(Vec::new(), 1.0, 0.0, DEFAULT_CELL).expect("no shapes with valid coefficients")
+    }
+}
+
+impl Obstacles {
+    pub fn new(shapes: Vec<Shape>, restitution: Real, friction: Real, cell: Real) -> io::Result<Self> {
+        /*
+        Bins the shapes into a grid of the given cell size. A fine grid over a large extent
+        would need too many cells, so the cells are doubled until there are at most MAX_CELLS.
+        Fails with InvalidData for a cell that isn't positive, coordinates that aren't finite,
+        restitution outside of [0, 1] or negative friction
+         */
+        let bad = |what: String| io::Error::new(io::ErrorKind::InvalidData, what);
+        if !(cell > 0.0 && cell.is_finite()) {
+            return Err(bad(format!("obstacle grid cell must be positive, got {cell}")));
+        }
+        if !(0.0..=1.0).contains(&restitution) || !(0.0..).contains(&friction) {
+            return Err(bad(format!("restitution {restitution} must be in [0, 1] and friction {friction} at least 0")));
+        }
+        let (mut lo, mut hi) = (Point { x: Real::INFINITY, y: Real::INFINITY }, Point { x: Real::NEG_INFINITY, y: Real::NEG_INFINITY });
+        for s in &shapes {
+            let (a, b) = s.bounds();
+            lo = Point { x: lo.x.min(a.x), y: lo.y.min(a.y) };
+            hi = Point { x: hi.x.max(b.x), y: hi.y.max(b.y) };
+        }
+        let mut cell: Real = cell;
+        let (cols, rows) = if shapes.is_empty() {
+            (0, 0)
+        } else {
+            if !((hi.x - lo.x).is_finite() && (hi.y - lo.y).is_finite()) {
+                return Err(bad("obstacle coordinates must be finite".to_string()));
+            }
+            let side = |extent: Real, cell: Real| (extent / cell).floor() + 1.0;
+            while side(hi.x - lo.x, cell) * side(hi.y - lo.y, cell) > MAX_CELLS {
+                cell *= 2.0;
+            }
+            (side(hi.x - lo.x, cell) as usize, side(hi.y - lo.y, cell) as usize)
+        };
+        let mut cells: Vec<Vec<u32>> = vec![Vec::new(); cols * rows];
+        let reach2: Real = 0.5 * cell * cell; // half the cell diagonal, squared
+        for (k, s) in shapes.iter().enumerate() {
+            let (a, b) = s.bounds();
+            let (c0, r0) = (((a.x - lo.x) / cell) as usize, ((a.y - lo.y) / cell) as usize);
+            let (c1, r1) = (((b.x - lo.x) / cell) as usize, ((b.y - lo.y) / cell) as usize);
+            for row in r0..=r1.min(rows - 1) {
+                for col in c0..=c1.min(cols - 1) {
+                    let mid: Point = Point { x: lo.x + (col as Real + 0.5) * cell, y: lo.y + (row as Real + 0.5) * cell };
+                    let q: Point = s.closest(mid);
+                    let (dx, dy) = (q.x - mid.x, q.y - mid.y);
+                    // a solid shape also covers the cells inside it
+                    if s.inside(mid) || dx * dx + dy * dy <= reach2 {
+                        cells[row * cols + col].push(k as u32);
+                    }
+                }
+            }
+        }
+        let mut starts: Vec<u32> = vec![0];
+        let mut items: Vec<u32> = Vec::new();
+        for c in cells {
+            items.extend(c);
+            starts.push(items.len() as u32);
+        }
+        Ok(Obstacles { shapes, restitution, friction, cell, origin: lo, cols, rows, starts, items })
+    }
+
+    pub fn near(&self, p: Point, radius: Real, out: &mut Vec<u32>) {
+        /*
+        indices of the shapes that may be within radius of p (no duplicates)
+         */
+        out.clear();
+        if self.cols == 0 {
+            return;
+        }
+        let to_cell = |v: Real, o: Real| ((v - o) / self.cell).floor();
+        let (c0, c1) = (to_cell(p.x - radius, self.origin.x), to_cell(p.x + radius, self.origin.x));
+        let (r0, r1) = (to_cell(p.y - radius, self.origin.y), to_cell(p.y + radius, self.origin.y));
+        if c1 < 0.0 || r1 < 0.0 || c0 >= self.cols as Real || r0 >= self.rows as Real {
+            return;
+        }
+        let (c0, r0) = (c0.max(0.0) as usize, r0.max(0.0) as usize);
+        let (c1, r1) = ((c1 as usize).min(self.cols - 1), (r1 as usize).min(self.rows - 1));
+        for row in r0..=r1 {
+            for col in c0..=c1 {
+                let c: usize = row * self.cols + col;
+                out.extend_from_slice(&self.items[self.starts[c] as usize..self.starts[c + 1] as usize]);
+            }
+        }
+        out.sort_unstable();
+        out.dedup();
+    }
+
+    fn bounce(&self, part: &mut Particle, shape: &Shape, radius: Real) -> bool {
+        /*
+        Pushes the particle out of the shape and reflects its velocity off the contact normal
+         */
+        let p: Point = part.position;
+        let q: Point = shape.closest(p);
+        let (dx, dy) = (p.x - q.x, p.y - q.y);
+        let d2: Real = dx * dx + dy * dy;
+        // inside a solid shape counts as touching even when far from its outline,
+        // and the way out is toward the closest outline point
+        let inside: bool = shape.inside(p);
+        if d2 >= radius * radius && !inside {
+            return false;
+        }
+        let d: Real = d2.sqrt();
+        let (nx, ny) = match (inside, d == 0.0) {
+            (_, true) => shape.outward(p),
+            (true, false) => (-dx / d, -dy / d),
+            (false, false) => (dx / d, dy / d),
+        };
+        let depth: Real = if inside { radius + d } else { radius - d };
+        part.position.x += nx * depth;
+        part.position.y += ny * depth;
+        self.reflect(part, nx, ny, radius);
+        true
+    }
+
+    fn reflect(&self, part: &mut Particle, nx: Real, ny: Real, radius: Real) {
+        /*
+        Reflects the velocity of a particle touching a shape off the outward contact normal,
+        with the restitution and Coulomb friction of the obstacles (the shape doesn't move)
+         */
+        let vn: Real = part.velocity.x * nx + part.velocity.y * ny;
+        if vn >= 0.0 {
+            return;
+        }
+        let j: Real = -(1.0 + self.restitution) * vn * part.mass; // normal impulse
+        part.velocity.x += j / part.mass * nx;
+        part.velocity.y += j / part.mass * ny;
+
+        if self.friction > 0.0 {
+            // sliding speed of the contact point, radius behind the center
+            let (tx, ty) = (-ny, nx);
+            let omega: Real = part.spin.map_or(0.0, |s| s.omega);
+            let inv_i: Real = part.spin.map_or(0.0, |s| 1.0 / s.inertia);
+            let vt: Real = part.velocity.x * tx + part.velocity.y * ty - radius * omega;
+            let k: Real = 1.0 / part.mass + radius * radius * inv_i;
+            let jt: Real = (-vt / k).clamp(-self.friction * j, self.friction * j);
+            part.velocity = part.velocity + Vector { x: tx, y: ty }.mult_scalar(jt / part.mass);
+            if let Some(s) = &mut part.spin {
+                s.omega -= radius * jt * inv_i;
+            }
+        }
+    }
+
+    pub fn collide(&self, particles: &mut [Particle], radius: Real, parallel: bool) -> u32 {
+        /*
+        Resolves every particle against the shapes near it, returns the number of contacts.
+        Each particle only touches itself, so the parallel loop gives the same bits
+         */
+        if self.shapes.is_empty() {
+            return 0;
+        }
+        let hit = |part: &mut Particle, out: &mut Vec<u32>| -> u32 {
+            self.near(part.position, radius, out);
+            out.iter().filter(|&&k| self.bounce(part, &self.shapes[k as usize], radius)).count() as u32
+        };
+        if parallel {
+            particles.par_iter_mut().map_init(Vec::new, |out, p| hit(p, out)).sum()
+        } else {
+            let mut out: Vec<u32> = Vec::new();
+            particles.iter_mut().map(|p| hit(p, &mut out)).sum()
+        }
+    }
+
+    pub fn drift(&self, particles: &mut [Particle], radius: Real, dt: Real, parallel: bool) -> u32 {
+        /*
+        Moves every particle by its velocity over dt like a plain drift, but a particle whose path
+        touches a shape stops there, bounces, and goes on with what is left of dt, so a fast one
+        can't pass through a thin wall. Contact times come from collision::contact_time.
+        Returns the number of bounces
+         */
+        let hit = |part: &mut Particle, out: &mut Vec<u32>| -> u32 {
+            let mut left: Real = dt;
+            let mut bounces: u32 = 0;
+            while !self.shapes.is_empty() {
+                // the shapes near the path: a disk around its middle reaching both ends
+                let v: Vector = part.velocity;
+                let mid: Point = part.position.add_vec(&v.mult_scalar(0.5 * left));
+                self.near(mid, 0.5 * v.x.hypot(v.y) * left + radius, out);
+                let first: Option<(Real, usize)> = out
+                    .iter()
+                    .filter_map(|&k| self.shapes[k as usize].time_of_impact(part.position, v, radius, left).map(|t| (t, k as usize)))
+                    .min_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+                let Some((t, k)) = first else {
+                    break;
+                };
+                part.position = part.position.add_vec(&v.mult_scalar(t));
+                left -= t;
+                bounces += 1;
+                if bounces > MAX_BOUNCES {
+                    return bounces - 1; // wedged, wait at the contact
+                }
+                let shape: &Shape = &self.shapes[k];
+                let q: Point = shape.closest(part.position);
+                let (dx, dy) = (part.position.x - q.x, part.position.y - q.y);
+                let d: Real = dx.hypot(dy);
+                let (nx, ny) = if d == 0.0 { shape.outward(part.position) } else { (dx / d, dy / d) };
+                self.reflect(part, nx, ny, radius);
+            }
+            part.position = part.position.add_vec(&part.velocity.mult_scalar(left));
+            bounces
+        };
+        if parallel {
+            particles.par_iter_mut().map_init(Vec::new, |out, p| hit(p, out)).sum()
+        } else {
+            let mut out: Vec<u32> = Vec::new();
+            particles.iter_mut().map(|p| hit(p, &mut out)).sum()
+        }
+    }
+
+    pub fn outline(&self) -> Vec<(Point, Point)> {
+        /*
+        line segments to draw, circles as polygons
+         */
+        let mut lines: Vec<(Point, Point)> = Vec::new();
+        for s in &self.shapes {
+            match s {
+                Shape::Segment { a, b } => lines.push((*a, *b)),
+                Shape::Polygon { corners } => lines.extend(edges(corners)),
+                Shape::Circle { center, radius } => {
+                    let step: Real = 2.0 * std::f64::consts::PI as Real / CIRCLE_SIDES as Real;
+                    let at = |k: usize| Point {
+                        x: center.x + radius * (k as Real * step).cos(),
+                        y: center.y + radius * (k as Real * step).sin(),
+                    };
+                    lines.extend((0..CIRCLE_SIDES).map(|k| (at(k), at(k + 1))));
+                }
+            }
+        }
+        lines
+    }
+}
+
+pub fn read_obstacles(input: impl BufRead) -> io::Result<Obstacles> {
+    /*
+    Reads an obstacle file: one item per line, `#` starts a comment.
+        segment x1 y1 x2 y2
+        polygon x1 y1 x2 y2 x3 y3 ...   (closed, at least 3 corners)
+        circle x y radius
+        restitution e                   (in [0, 1], 1 by default)
+        friction mu                     (at least 0, 0 by default)
+        cell size                       (broad-phase grid cell > 0, 10 by default)
+     */
+    let bad = |line: usize, what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("line {line}: {what}"));
+    let mut shapes: Vec<Shape> = Vec::new();
+    let (mut restitution, mut friction, mut cell): (Real, Real, Real) = (1.0, 0.0, DEFAULT_CELL);
+    for (n, line) in input.lines().enumerate() {
+        let line: String = line?;
+        let line: &str = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let kind: &str = words.next().unwrap_or("");
+        let nums: Vec<Real> = words
+            .map(|w| w.parse::<Real>().ok().filter(|x| x.is_finite()).ok_or_else(|| bad(n + 1, "not a number")))
+            .collect::<io::Result<Vec<Real>>>()?;
+        let point = |k: usize| Point { x: nums[2 * k], y: nums[2 * k + 1] };
+        match (kind, nums.len()) {
+            ("segment", 4) => shapes.push(Shape::Segment { a: point(0), b: point(1) }),
+            ("polygon", len) if len >= 6 && len % 2 == 0 => {
+                shapes.push(Shape::Polygon { corners: (0..len / 2).map(point).collect() });
+            }
+            ("circle", 3) => shapes.push(Shape::Circle { center: point(0), radius: nums[2] }),
+            ("restitution", 1) if !(0.0..=1.0).contains(&nums[0]) => return Err(bad(n + 1, "restitution must be in [0, 1]")),
+            ("restitution", 1) => restitution = nums[0],
+            ("friction", 1) if nums[0] < 0.0 => return Err(bad(n + 1, "friction can't be negative")),
+            ("friction", 1) => friction = nums[0],
+            ("cell", 1) if nums[0] > 0.0 => cell = nums[0],
+            _ => return Err(bad(n + 1, "expected segment, polygon, circle, restitution, friction or cell")),
+        }
+    }
+    Obstacles::new(shapes, restitution, friction, cell)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    fn ball(x: Real, y: Real, vx: Real, vy: Real) -> Particle {
+        Particle::new(0, Point::new(x, y), Vector { x: vx, y: vy }, 1.0)
+    }
+
+    const FILE: &str = "
+        # a pen with a triangle and a post inside
+        restitution 0.5
+        friction 0.2
+        cell 4
+        segment -20 -20 20 -20
+        segment 20 -20 20 20
+        segment 20 20 -20 20
+        segment -20 20 -20 -20
+        polygon 0 0  5 0  0 5   # triangle
+        circle -10 10 3
+        segment -15 -5 -5 -15
+    ";
+
+    #[test]
+    fn test_read_obstacles() {
+        let obstacles: Obstacles = read_obstacles(FILE.as_bytes()).unwrap();
+        assert_eq!(obstacles.shapes.len(), 4 + 1 + 1 + 1);
+        assert_eq!((obstacles.restitution, obstacles.friction), (0.5, 0.2));
+        assert_eq!(obstacles.outline().len(), 8 + CIRCLE_SIDES);
+        assert!(read_obstacles("circle 1 2".as_bytes()).is_err());
+        assert!(read_obstacles("wall 1 2 3 4".as_bytes()).is_err());
+        assert!(read_obstacles("cell 0".as_bytes()).is_err());
+        assert!(read_obstacles("cell -2".as_bytes()).is_err());
+        assert!(read_obstacles("circle 0 0 inf".as_bytes()).is_err());
+        assert!(read_obstacles("restitution 1.5".as_bytes()).is_err());
+        assert!(read_obstacles("restitution -0.1".as_bytes()).is_err());
+        assert!(read_obstacles("friction -0.2".as_bytes()).is_err());
+        let segment: Vec<Shape> = vec![Shape::Segment { a: Point::zero(), b: Point::new(1.0, 0.0) }];
+        assert!(Obstacles::new(segment.clone(), 1.0, 0.0, 0.0).is_err());
+        assert!(Obstacles::new(segment.clone(), 1.0, 0.0, Real::NAN).is_err());
+        assert!(Obstacles::new(segment.clone(), 2.0, 0.0, 1.0).is_err());
+        assert!(Obstacles::new(segment.clone(), 1.0, -1.0, 1.0).is_err());
+        assert!(Obstacles::new(vec![Shape::Circle { center: Point::new(Real::INFINITY, 0.0), radius: 1.0 }], 1.0, 0.0, 1.0).is_err());
+        assert!(Obstacles::new(segment, 1.0, 0.0, 1.0).is_ok());
+    }
+
+    #[test]
+    fn test_grid_size_is_bounded() {
+        // a tiny cell over a huge extent widens the cells instead of allocating billions
+        let wide: Obstacles = read_obstacles("cell 0.001\nsegment -1e6 -1e6 1e6 1e6".as_bytes()).unwrap();
+        assert!((wide.cols * wide.rows) as Real <= MAX_CELLS && wide.cell > 0.001);
+        let mut out: Vec<u32> = Vec::new();
+        wide.near(Point::new(5.0, 5.0), 1.0, &mut out);
+        assert_eq!(out, vec![0]);
+        // a grid that fits keeps its cell
+        let small: Obstacles = read_obstacles("cell 0.5\nsegment 0 0 10 10".as_bytes()).unwrap();
+        assert_eq!((small.cell, small.cols, small.rows), (0.5, 21, 21));
+    }
+
+    #[test]
+    fn test_grid_finds_every_close_shape() {
+        // the grid never misses a shape a brute force check would hit
+        let obstacles: Obstacles = read_obstacles(FILE.as_bytes()).unwrap();
+        let mut rng: Rng = Rng::new(3);
+        let mut out: Vec<u32> = Vec::new();
+        for _ in 0..2000 {
+            let p: Point = Point::new(-25.0 + 50.0 * rng.uniform(), -25.0 + 50.0 * rng.uniform());
+            let radius: Real = 0.2 + 2.0 * rng.uniform();
+            obstacles.near(p, radius, &mut out);
+            for (k, s) in obstacles.shapes.iter().enumerate() {
+                if s.closest(p).distance(&p) < radius {
+                    assert!(out.contains(&(k as u32)), "shape {k} missed at ({}, {})", p.x, p.y);
+                }
+            }
+            // and skips most of them
+            assert!(out.len() < obstacles.shapes.len());
+        }
+    }
+
+    #[test]
+    fn test_bounce() {
+        let floor: Obstacles = Obstacles::new(vec![Shape::Segment { a: Point::new(-10.0, 0.0), b: Point::new(10.0, 0.0) }], 0.5, 0.0, 4.0).unwrap();
+        // falling onto the floor at an angle, slightly sunk in
+        let mut particles: Vec<Particle> = vec![ball(1.0, 0.8, 2.0, -4.0), ball(30.0, 0.5, 0.0, -1.0)];
+        assert_eq!(floor.collide(&mut particles, 1.0, false), 1);
+        assert!((particles[0].position.y - 1.0).abs() < 1e-6);
+        assert_eq!((particles[0].velocity.x, particles[0].velocity.y), (2.0, 2.0));
+        assert_eq!(particles[1].velocity.y, -1.0); // out of reach
+
+        // a ball inside a post is pushed out to its surface
+        let post: Obstacles = Obstacles::new(vec![Shape::Circle { center: Point::zero(), radius: 3.0 }], 1.0, 0.0, 4.0).unwrap();
+        let mut inside: Vec<Particle> = vec![ball(1.0, 0.0, -1.0, 0.0)];
+        post.collide(&mut inside, 1.0, true);
+        assert!((inside[0].position.x - 4.0).abs() < 1e-5 && inside[0].velocity.x == 1.0);
+    }
+
+    #[test]
+    fn test_drift_is_swept() {
+        // a thin wall and a block: far faster than their thickness, nothing gets through
+        let shapes: Vec<Shape> = vec![
+            Shape::Segment { a: Point::new(0.0, -10.0), b: Point::new(0.0, 10.0) },
+            Shape::Polygon { corners: vec![Point::new(-10.0, 20.0), Point::new(10.0, 20.0), Point::new(0.0, 22.0)] },
+        ];
+        let walls: Obstacles = Obstacles::new(shapes, 1.0, 0.0, 4.0).unwrap();
+        let mut particles: Vec<Particle> = vec![ball(-3.0, 0.0, 100.0, 0.0), ball(3.0, 14.0, 0.0, 200.0), ball(-3.0, 30.0, 10.0, 0.0)];
+        assert_eq!(walls.drift(&mut particles, 1.0, 0.1, false), 2);
+        // 2 units to the wall, back 8 with the rest of the step
+        assert!((particles[0].position.x + 9.0).abs() < 1e-4 && particles[0].velocity.x == -100.0);
+        // 5 units to the block's floor, back 15
+        assert!((particles[1].position.y - 4.0).abs() < 1e-4 && particles[1].velocity.y == -200.0);
+        // a miss is a plain drift
+        assert_eq!((particles[2].position.x, particles[2].position.y), (-2.0, 30.0));
+
+        // between two walls it bounces as often as it hits them, in order
+        let corridor: Obstacles = Obstacles::new(
+            vec![
+                Shape::Segment { a: Point::new(0.0, -10.0), b: Point::new(0.0, 10.0) },
+                Shape::Segment { a: Point::new(4.0, -10.0), b: Point::new(4.0, 10.0) },
+            ],
+            1.0,
+            0.0,
+            4.0,
+        ).unwrap();
+        let mut rattler: Vec<Particle> = vec![ball(2.0, 0.0, 10.0, 0.0)];
+        assert_eq!(corridor.drift(&mut rattler, 1.0, 0.6, true), 3);
+        assert!((rattler[0].position.x - 2.0).abs() < 1e-4 && rattler[0].velocity.x == -10.0);
+    }
+
+    #[test]
+    fn test_polygon_is_solid() {
+        // a particle inside a polygon leaves through the nearest edge, whatever the winding
+        let ccw: Vec<Point> = vec![Point::new(0.0, 0.0), Point::new(10.0, 0.0), Point::new(10.0, 10.0), Point::new(0.0, 10.0)];
+        let cw: Vec<Point> = ccw.iter().rev().copied().collect();
+        for corners in [ccw, cw] {
+            let block: Obstacles = Obstacles::new(vec![Shape::Polygon { corners }], 1.0, 0.0, 4.0).unwrap();
+            let mut particles: Vec<Particle> = vec![ball(2.0, 5.0, 3.0, 0.0), ball(5.0, 9.0, 0.0, 1.0), ball(5.0, 20.0, 0.0, -1.0)];
+            assert_eq!(block.collide(&mut particles, 1.0, false), 2);
+            assert!((particles[0].position.x + 1.0).abs() < 1e-5 && particles[0].velocity.x == -3.0);
+            assert!((particles[1].position.y - 11.0).abs() < 1e-5 && particles[1].velocity.y == 1.0);
+            assert_eq!(particles[2].position.y, 20.0); // well clear of it
+            // on the outline exactly, it still leaves outward
+            let mut edge: Vec<Particle> = vec![ball(10.0, 4.0, -1.0, 0.0)];
+            block.collide(&mut edge, 1.0, false);
+            assert!((edge[0].position.x - 11.0).abs() < 1e-5 && edge[0].velocity.x == 1.0);
+        }
+    }
+}
@@ -0,0 +1,354 @@
+use crate::Real;
+use crate::quadtree::{Point, QuadTree};
+use crate::vector::{Particle, Vector};
+use rayon::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Potential {
+    /*
+    Short-range pair potential U(r) between two particles
+     */
+    LennardJones { epsilon: Real, sigma: Real },    // 4 eps ((sigma / r)^12 - (sigma / r)^6), minimum -eps at 2^(1/6) sigma
+    Morse { depth: Real, width: Real, r0: Real },   // depth ((1 - e^(-width (r - r0)))^2 - 1), minimum -depth at r0
+    SoftSphere { epsilon: Real, sigma: Real, n: i32 }, // eps (sigma / r)^n, purely repulsive
+}
+
+impl Potential {
+    fn energy(self, r2: Real) -> Real {
+        match self {
+            Potential::LennardJones { epsilon, sigma } => {
+                let s6: Real = (sigma * sigma / r2).powi(3);
+                4.0 * epsilon * (s6 * s6 - s6)
+            }
+            Potential::Morse { depth, width, r0 } => {
+                let e: Real = (-width * (r2.sqrt() - r0)).exp();
+                depth * ((1.0 - e) * (1.0 - e) - 1.0)
+            }
+            Potential::SoftSphere { epsilon, sigma, n } => epsilon * (sigma / r2.sqrt()).powi(n),
+        }
+    }
+
+    fn force_over_r(self, r2: Real) -> Real {
+        // -dU/dr / r, positive when repulsive
+        match self {
+            Potential::LennardJones { epsilon, sigma } => {
+                let s6: Real = (sigma * sigma / r2).powi(3);
+                24.0 * epsilon * (2.0 * s6 * s6 - s6) / r2
+            }
+            Potential::Morse { depth, width, r0 } => {
+                let r: Real = r2.sqrt();
+                let e: Real = (-width * (r - r0)).exp();
+                -2.0 * depth * width * e * (1.0 - e) / r
+            }
+            Potential::SoftSphere { epsilon, sigma, n } => n as Real * epsilon * (sigma / r2.sqrt()).powi(n) / r2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PairForce {
+    /*
+    A potential cut off at cutoff: no force beyond it, and the energy is shifted so it
+    is 0 there
+     */
+    pub potential: Potential,
+    pub cutoff: Real,
+}
+
+impl PairForce {
+    pub fn force_over_r(&self, r2: Real) -> Real {
+        /*
+        Force along the offset divided by the distance: the acceleration of a particle of
+        mass m at offset d from the other one is d * force_over_r / m. 0 at r = 0
+         */
+        if r2 >= self.cutoff * self.cutoff || r2 == 0.0 {
+            return 0.0;
+        }
+        self.potential.force_over_r(r2)
+    }
+
+    pub fn energy(&self, r2: Real) -> Real {
+        if r2 >= self.cutoff * self.cutoff || r2 == 0.0 {
+            return 0.0;
+        }
+        self.potential.energy(r2) - self.potential.energy(self.cutoff * self.cutoff)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NeighborList {
+    /*
+    Verlet list: every particle's neighbors within cutoff + skin, found with the tree.
+    It stays valid until some particle has moved more than skin / 2 since it was built
+    (two particles closing in can't cross the cutoff unseen before then), so most steps
+    reuse it. Neighbors are stored by particle index, so it is also rebuilt when the
+    particles were reordered
+     */
+    pub cutoff: Real, // the cutoff of the PairForce it serves, pair_accels checks that it covers it
+    pub skin: Real,
+    starts: Vec<u32>,    // neighbors of particle i are neighbors[starts[i]..starts[i + 1]]
+    neighbors: Vec<u32>,
+    anchors: Vec<Point>, // positions at the last build
+    ids: Vec<u64>,       // particle ids at the last build
+    pub builds: u64,     // number of times the list was rebuilt
+}
+
+impl NeighborList {
+    pub fn new(pair: &PairForce, skin: Real) -> Self {
+        /*
+        a list for pair, reaching skin past its cutoff
+         */
+        NeighborList {
+            cutoff: pair.cutoff,
+            skin,
+            starts: Vec::new(),
+            neighbors: Vec::new(),
+            anchors: Vec::new(),
+            ids: Vec::new(),
+            builds: 0,
+        }
+    }
+
+    pub fn of(&self, i: usize) -> &[u32] {
+        /*
+        neighbors of particle i, empty for particles that were outside the tree
+         */
+        &self.neighbors[self.starts[i] as usize..self.starts[i + 1] as usize]
+    }
+
+    pub fn is_stale(&self, particles: &[Particle]) -> bool {
+        let half: Real = 0.5 * self.skin;
+        particles.len() != self.ids.len()
+            || particles.iter().zip(&self.ids).zip(&self.anchors).any(|((p, &id), a)| {
+                let (dx, dy) = (p.position.x - a.x, p.position.y - a.y);
+                p.id != id || dx * dx + dy * dy > half * half
+            })
+    }
+
+    pub fn update(&mut self, particles: &[Particle], tree: &QuadTree) -> bool {
+        /*
+        Rebuilds the list from tree if it is stale, tree must have been built from particles.
+        Returns whether it was rebuilt
+         */
+        if !self.is_stale(particles) {
+            return false;
+        }
+        let reach: Real = self.cutoff + self.skin;
+        let find = |(i, p): (usize, &Particle), out: &mut Vec<u32>| -> Vec<u32> {
+            out.clear();
+            // particles outside the tree get no neighbors, so every pair is seen from both sides
+            if tree.area.contains(&p.position) {
+                tree.query_radius(p.position, reach, out);
+            }
+            let mut mine: Vec<u32> = out.iter().copied().filter(|&j| j as usize != i).collect();
+            mine.sort_unstable();
+            mine
+        };
+        let lists: Vec<Vec<u32>> = if tree.parallel {
+            particles.par_iter().enumerate().map_init(Vec::new, |out, ip| find(ip, out)).collect()
+        } else {
+            let mut out: Vec<u32> = Vec::new();
+            particles.iter().enumerate().map(|ip| find(ip, &mut out)).collect()
+        };
+
+        self.starts.clear();
+        self.neighbors.clear();
+        self.starts.push(0);
+        for list in lists {
+            self.neighbors.extend(list);
+            self.starts.push(self.neighbors.len() as u32);
+        }
+        self.anchors.clear();
+        self.anchors.extend(particles.iter().map(|p| p.position));
+        self.ids.clear();
+        self.ids.extend(particles.iter().map(|p| p.id));
+        self.builds += 1;
+        true
+    }
+}
+
+pub fn pair_accels(
+    particles: &[Particle],
+    tree: &QuadTree,
+    pair: &PairForce,
+    list: Option<&NeighborList>,
+    parallel: bool,
+) -> Vec<Vector> {
+    /*
+    Acceleration of every particle from the pair force. Uses list if given, it must be up to
+    date with particles, otherwise searches the tree within the cutoff for every particle.
+    Panics if the list reaches less far than the cutoff, it would drop pairs
+     */
+    if let Some(list) = list {
+        assert!(
+            list.cutoff >= pair.cutoff,
+            "neighbor list cutoff {} is shorter than the pair cutoff {}",
+            list.cutoff,
+            pair.cutoff
+        );
+    }
+    let accel_on = |i: usize, others: &mut dyn Iterator<Item = u32>| -> Vector {
+        let p: &Particle = &particles[i];
+        let mut a: Vector = Vector { x: 0.0, y: 0.0 };
+        for j in others {
+            let q: Point = particles[j as usize].position;
+            let (dx, dy) = (p.position.x - q.x, p.position.y - q.y);
+            let f: Real = pair.force_over_r(dx * dx + dy * dy);
+            a.x += f * dx;
+            a.y += f * dy;
+        }
+        a.mult_scalar(1.0 / p.mass)
+    };
+    let accel_of = |i: usize, out: &mut Vec<u32>| -> Vector {
+        match list {
+            Some(list) => accel_on(i, &mut list.of(i).iter().copied()),
+            None => {
+                out.clear();
+                if tree.area.contains(&particles[i].position) {
+                    tree.query_radius(particles[i].position, pair.cutoff, out);
+                }
+                accel_on(i, &mut out.iter().copied().filter(|&j| j as usize != i))
+            }
+        }
+    };
+    if parallel {
+        (0..particles.len()).into_par_iter().map_init(Vec::new, |out, i| accel_of(i, out)).collect()
+    } else {
+        let mut out: Vec<u32> = Vec::new();
+        (0..particles.len()).map(|i| accel_of(i, &mut out)).collect()
+    }
+}
+
+pub fn pair_energy(particles: &[Particle], tree: &QuadTree, pair: &PairForce) -> f64 {
+    /*
+    total pair potential energy, for energy diagnostics. tree must have been built from particles
+     */
+    let mut e: f64 = 0.0;
+    let mut out: Vec<u32> = Vec::new();
+    for (i, p) in particles.iter().enumerate() {
+        out.clear();
+        if tree.area.contains(&p.position) {
+            tree.query_radius(p.position, pair.cutoff, &mut out);
+        }
+        for &j in out.iter().filter(|&&j| j as usize > i) {
+            let q: Point = particles[j as usize].position;
+            let (dx, dy) = (p.position.x - q.x, p.position.y - q.y);
+            let u: Real = pair.energy(dx * dx + dy * dy);
+            e += u as f64;
+        }
+    }
+    e
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quadtree::{Rectangle, build_tree};
+
+    const POTENTIALS: [Potential; 3] = [
+        Potential::LennardJones { epsilon: 1.5, sigma: 1.0 },
+        Potential::Morse { depth: 2.0, width: 1.3, r0: 1.2 },
+        Potential::SoftSphere { epsilon: 1.0, sigma: 1.0, n: 12 },
+    ];
+
+    #[test]
+    fn test_force_is_minus_gradient() {
+        for potential in POTENTIALS {
+            let pair: PairForce = PairForce { potential, cutoff: 3.0 };
+            for k in 0..40 {
+                let r: f64 = 0.95 + k as f64 * 0.05;
+                let dr: f64 = 1e-3;
+                let u = |r: f64| pair.energy((r * r) as Real) as f64;
+                let slope: f64 = (u(r + dr) - u(r - dr)) / (2.0 * dr);
+                let f: f64 = pair.force_over_r((r * r) as Real) as f64 * r;
+                assert!((f + slope).abs() < 2e-3 * (1.0 + f.abs()), "{potential:?} at r = {r}: {f} vs {}", -slope);
+            }
+        }
+    }
+
+    #[test]
+    fn test_minimum_and_cutoff() {
+        let lj: PairForce = PairForce { potential: POTENTIALS[0], cutoff: 2.5 };
+        let r_min: Real = Real::powf(2.0, 1.0 / 6.0);
+        assert!(lj.force_over_r(r_min * r_min).abs() < 1e-3);
+        let morse: PairForce = PairForce { potential: POTENTIALS[1], cutoff: 4.0 };
+        assert!(morse.force_over_r(1.44).abs() < 1e-4);
+        assert!(morse.force_over_r(1.0) > 0.0 && morse.force_over_r(2.0) < 0.0);
+        // nothing at or past the cutoff, and nothing for coincident particles
+        for pair in [lj, morse] {
+            let c2: Real = pair.cutoff * pair.cutoff;
+            assert_eq!((pair.force_over_r(c2), pair.energy(c2)), (0.0, 0.0));
+            assert_eq!(pair.force_over_r(0.0), 0.0);
+        }
+    }
+
+    fn lattice() -> Vec<Particle> {
+        let mut particles: Vec<Particle> = Vec::new();
+        for i in 0..20 {
+            for j in 0..15 {
+                let jitter: Real = ((i * 7 + j * 13) % 10) as Real * 0.03;
+                let position: Point = Point::new(i as Real * 1.1 + jitter, j as Real * 1.1 - jitter);
+                particles.push(Particle::new((i * 15 + j) as u64, position, Vector { x: 0.0, y: 0.0 }, 1.0));
+            }
+        }
+        particles
+    }
+
+    #[test]
+    fn test_neighbor_list_matches_brute_force() {
+        let bounds: Rectangle = Rectangle::new(Point::new(-5.0, -5.0), Point::new(30.0, 30.0));
+        let mut particles: Vec<Particle> = lattice();
+        let pair: PairForce = PairForce { potential: POTENTIALS[0], cutoff: 2.5 };
+        let mut list: NeighborList = NeighborList::new(&pair, 0.4);
+        let tree: QuadTree = build_tree(&particles, bounds);
+        assert!(list.update(&particles, &tree));
+        for (i, p) in particles.iter().enumerate() {
+            let mut expected: Vec<u32> = (0..particles.len() as u32)
+                .filter(|&j| j as usize != i && p.position.distance(&particles[j as usize].position) <= 2.9)
+                .collect();
+            expected.sort_unstable();
+            assert_eq!(list.of(i), &expected[..]);
+        }
+
+        // small moves keep the list, a move past skin / 2 or a reorder rebuilds it
+        particles[3].position.x += 0.15;
+        assert!(!list.update(&particles, &build_tree(&particles, bounds)));
+        particles[3].position.x += 0.1;
+        assert!(list.update(&particles, &build_tree(&particles, bounds)));
+        particles.swap(0, 1);
+        assert!(list.update(&particles, &build_tree(&particles, bounds)));
+        assert_eq!(list.builds, 3);
+    }
+
+    #[test]
+    fn test_list_and_tree_search_agree() {
+        let bounds: Rectangle = Rectangle::new(Point::new(-5.0, -5.0), Point::new(30.0, 30.0));
+        let particles: Vec<Particle> = lattice();
+        let pair: PairForce = PairForce { potential: POTENTIALS[0], cutoff: 2.5 };
+        let tree: QuadTree = build_tree(&particles, bounds);
+        let direct: Vec<Vector> = pair_accels(&particles, &tree, &pair, None, false);
+        let mut list: NeighborList = NeighborList::new(&pair, 0.4);
+        list.update(&particles, &tree);
+        let listed: Vec<Vector> = pair_accels(&particles, &tree, &pair, Some(&list), true);
+        let mut net: Vector = Vector { x: 0.0, y: 0.0 };
+        for (a, b) in direct.iter().zip(&listed) {
+            assert!((a.x - b.x).abs() < 1e-3 * (1.0 + a.x.abs()) && (a.y - b.y).abs() < 1e-3 * (1.0 + a.y.abs()));
+            net = net + *a;
+        }
+        // Newton's third law, equal masses
+        let scale: Real = direct.iter().map(|a| a.x.abs() + a.y.abs()).sum();
+        assert!(net.x.abs() < 1e-4 * scale && net.y.abs() < 1e-4 * scale);
+    }
+
+    #[test]
+    #[should_panic(expected = "shorter than the pair cutoff")]
+    fn test_short_list_is_rejected() {
+        // a list built for a shorter potential would silently drop the pairs beyond it
+        let bounds: Rectangle = Rectangle::new(Point::new(-5.0, -5.0), Point::new(30.0, 30.0));
+        let particles: Vec<Particle> = lattice();
+        let short: PairForce = PairForce { potential: POTENTIALS[0], cutoff: 1.5 };
+        let tree: QuadTree = build_tree(&particles, bounds);
+        let list: NeighborList = NeighborList::new(&short, 0.4);
+        pair_accels(&particles, &tree, &PairForce { cutoff: 2.5, ..short }, Some(&list), false);
+    }
+}
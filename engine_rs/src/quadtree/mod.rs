@@ -1,71 +1,77 @@
+use crate::Real;
+use crate::morton::{morton_key, quadrant_at, radix_sort};
 use crate::vector::{Vector, Particle};
+use rayon::prelude::*;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
 
 const MAX_DEPTH: u32 = 32;
-const MIN_SIZE: f32 = 0.01;
+const MIN_SIZE: Real = 0.01;
+const PARALLEL_LEVELS: u32 = 3; // a parallel build splits into up to 4^3 = 64 subtree tasks
 
 #[derive(Debug, Clone, Copy)]
 pub struct Point {
     /*
     Represents a point on a 2D plane
      */
-    pub x: f32,
-    pub y: f32,
+    pub x: Real,
+    pub y: Real,
 }
 
 impl Point {
-    pub fn new(x: f32, y: f32) -> Self {
-        Self { x: x, y: y }
+    pub fn new(x: Real, y: Real) -> Self {
+        Self { x, y }
     }
 
     pub fn zero() -> Self {
         Self { x: 0.0, y: 0.0 }
     }
 
-    pub fn midpoint(self: &Self, other: &Self) -> Self {
+    pub fn midpoint(&self, other: &Self) -> Self {
         Self {
             x: (self.x + other.x) / 2.0,
             y: (self.y + other.y) / 2.0,
         }
     }
 
-    pub fn div(self: &Self, divisor: f32) -> Self {
+    pub fn div(&self, divisor: Real) -> Self {
         Self {
             x: (self.x / divisor),
             y: (self.y / divisor),
         }
     }
 
-    pub fn mult_point(self: &Self, other: &Self) -> Self {
+    pub fn mult_point(&self, other: &Self) -> Self {
         Self {
             x: (self.x * other.x),
             y: (self.y * other.y),
         }
     }
 
-    pub fn mult_scalar(self: &Self, other: f32) -> Self {
+    pub fn mult_scalar(&self, other: Real) -> Self {
         Self {
             x: (self.x * other),
             y: (self.y * other),
         }
     }
 
-    pub fn diff(self: &Self, other: &Self) -> Self {
+    pub fn diff(&self, other: &Self) -> Self {
         Self {
             x: (self.x - other.x).abs(),
             y: (self.y - other.y).abs(),
         }
     }
 
-    pub fn add(self: &Self, other: &Self) -> Self {
+    pub fn add(&self, other: &Self) -> Self {
         Self {
             x: (self.x + other.x),
             y: (self.y + other.y),
         }
     }
 
-    pub fn distance(self: &Self, other: &Self) -> f32 {
-        let dx: f32 = self.x - other.x;
-        let dy: f32 = self.y - other.y;
+    pub fn distance(&self, other: &Self) -> Real {
+        let dx: Real = self.x - other.x;
+        let dy: Real = self.y - other.y;
         (dx.powi(2) + dy.powi(2)).sqrt()
     }
 
@@ -89,7 +95,7 @@ pub struct Rectangle {
 
 impl Rectangle {
     pub fn new(p1: Point, p2: Point) -> Self {
-        Self { p1: p1, p2: p2 }
+        Self { p1, p2 }
     }
 
     pub fn contains(&self, p: &Point) -> bool {
@@ -114,37 +120,92 @@ impl Rectangle {
         }
     }
 
+    pub fn quadrants(&self) -> [Rectangle; 4] {
+        /*
+        Splits the rectangle into its 4 quadrants: NW, NE, SW, SE
+         */
+        let p1: Point = self.p1;
+        let p2: Point = self.p2;
+        let mid: Point = self.center();
+
+        [
+            Rectangle { p1: Point { x: p1.x, y: mid.y }, p2: Point { x: mid.x, y: p2.y } },
+            Rectangle { p1: Point { x: mid.x, y: mid.y }, p2: Point { x: p2.x, y: p2.y } },
+            Rectangle { p1: Point { x: p1.x, y: p1.y }, p2: Point { x: mid.x, y: mid.y } },
+            Rectangle { p1: Point { x: mid.x, y: p1.y }, p2: Point { x: p2.x, y: mid.y } },
+        ]
+    }
+
     pub fn intersects(&self, rect: &Rectangle) -> bool {
-        self.p1.x <= rect.p1.x
-            && self.p2.x >= rect.p1.x
-            && self.p1.y <= rect.p2.y
-            && self.p2.y >= rect.p2.y
+        // overlap on both axes, touching edges count (same as contains)
+        self.p1.x <= rect.p2.x && rect.p1.x <= self.p2.x && self.p1.y <= rect.p2.y && rect.p1.y <= self.p2.y
+    }
+
+    pub fn contains_rect(&self, rect: &Rectangle) -> bool {
+        self.contains(&rect.p1) && self.contains(&rect.p2)
     }
 
-    pub fn distance_to_point(&self, p: &Point) -> f32 {
-        let px: f32 = p.x;
-        let py: f32 = p.y;
+    pub fn distance_to_point(&self, p: &Point) -> Real {
+        let px: Real = p.x;
+        let py: Real = p.y;
 
-        let closest_x: f32 = self.p1.x.max(px.min(self.p2.x));
-        let closest_y: f32 = self.p1.y.max(py.min(self.p2.y));
+        let closest_x: Real = self.p1.x.max(px.min(self.p2.x));
+        let closest_y: Real = self.p1.y.max(py.min(self.p2.y));
 
-        let dx: f32 = px - closest_x;
-        let dy: f32 = py - closest_y;
+        let dx: Real = px - closest_x;
+        let dy: Real = py - closest_y;
 
         dx.powi(2) + dy.powi(2)
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct Node {
+    /*
+    One quadrant of the tree. Nodes live in QuadTree::nodes in depth-first order,
+    so a node's first child is always the next node in the array
+     */
+    pub area: Rectangle,
+    pub zones: [Option<u32>; 4], // NW, NE, SW, SE child indices
+    pub next: u32,               // index of the first node after this subtree
+    pub start: u32,              // this subtree's particles are index[start..end]
+    pub end: u32,
+    pub mass: Real,
+    pub cm: Point,
+    pub quad: [Real; 3], // quadrupole tensor about cm: [Qxx, Qxy, Qyy]
+    pub charge: Real,     // total charge, can cancel to 0
+    pub abs_charge: Real, // total absolute charge, for the opening criterion
+    pub qcenter: Point,   // center weighted by absolute charge (total charge may be 0)
+    pub dipole: Point,    // sum q (x - qcenter), what is left of a neutral node at a distance
+}
+
+impl Node {
+    pub fn is_leaf(&self) -> bool {
+        // checks if a node is a leaf
+        self.zones.iter().all(|z| z.is_none())
+    }
+}
+
 pub struct QuadTree {
     /*
-    A QuadTree is a tree that contains 4 children, it is used to represent quadrants in the simulation
+    A QuadTree is a tree where every node has 4 children, it is used to represent quadrants in the simulation.
+    The nodes and particle indices are stored in flat arrays that are reused every time the tree is rebuilt.
+    Leaves refer to particles by their index in the slice the tree was built from; their positions
+    are cached next to the indices so the force walks don't have to jump around the particle array
      */
     pub area: Rectangle,
     threshold: usize, // threshold will (should) always be 4, but setting default values isnt supported yet
-    pub zones: [Option<Box<QuadTree>>; 4],
-    pub elements: Vec<Point>,
-    pub mass: f32,
-    pub cm: Point
+    pub nodes: Vec<Node>,
+    pub index: Vec<u32>, // particle index of every element, in tree order
+    pub xs: Vec<Real>,   // element positions, split into x and y arrays
+    pub ys: Vec<Real>,
+    pub masses: Vec<Real>,  // element masses, in tree order like xs and ys
+    pub charges: Vec<Real>, // element charges, in tree order like xs and ys
+    points: Vec<Point>, // element positions while building
+    pub parallel: bool, // build the top levels' subtrees on the rayon thread pool
+    keys: Vec<u64>,     // Morton key of every element, only filled by rebuild_morton
+    keyed: Vec<(u64, (Point, u32))>,
+    scratch: Vec<(u64, (Point, u32))>,
 }
 
 impl QuadTree {
@@ -152,94 +213,745 @@ impl QuadTree {
         QuadTree {
             area: rect,
             threshold: 4,
-            zones: [None, None, None, None],
-            elements: Vec::new(),
-            mass: 1.0,
-            cm: Point::zero(),
+            nodes: Vec::new(),
+            index: Vec::new(),
+            xs: Vec::new(),
+            ys: Vec::new(),
+            masses: Vec::new(),
+            charges: Vec::new(),
+            points: Vec::new(),
+            parallel: false,
+            keys: Vec::new(),
+            keyed: Vec::new(),
+            scratch: Vec::new(),
         }
     }
 
-    fn is_leaf(&self) -> bool {
-        // checks if a quadtree is a leaf
-        self.zones.iter().all(|z| z.is_none())
+    pub fn root(&self) -> &Node {
+        &self.nodes[0]
     }
 
-    pub fn insert(&mut self, p: Point) {
+    pub fn node_indices(&self, node: &Node) -> &[u32] {
         /*
-        safe version that uses insert depth to prevent stack overflows
+        indices of all particles stored in the subtree under node
          */
-        self.insert_depth(p, 0);
+        &self.index[node.start as usize..node.end as usize]
     }
 
-    fn insert_depth(&mut self, p: Point, depth: u32) {
-        // If point isn't inside this node, ignore
-        if !self.area.contains(&p) {
-            return;
+    pub fn node_points(&self, node: &Node) -> impl Iterator<Item = Point> + '_ {
+        /*
+        positions of all particles in the subtree under node, in the same order as node_indices
+         */
+        let (start, end) = (node.start as usize, node.end as usize);
+        self.xs[start..end].iter().zip(&self.ys[start..end]).map(|(&x, &y)| Point { x, y })
+    }
+
+    pub fn node_particles<'a>(&'a self, node: &Node, particles: &'a [Particle]) -> impl Iterator<Item = &'a Particle> + 'a {
+        /*
+        the particles in the subtree under node, particles must be the slice the tree was built from
+         */
+        self.node_indices(node).iter().map(move |&i| &particles[i as usize])
+    }
+
+    pub fn len(&self) -> usize {
+        // number of particles in the tree (particles outside the bounds are not counted)
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn children<'a>(&'a self, node: &Node) -> impl Iterator<Item = &'a Node> + 'a {
+        /*
+        the existing children of node, in NW, NE, SW, SE order
+         */
+        node.zones.into_iter().flatten().map(move |i| &self.nodes[i as usize])
+    }
+
+    pub fn leaves(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.iter().filter(|n| n.is_leaf())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, Point)> + '_ {
+        /*
+        every particle in the tree exactly once as (index, position), leaf by leaf in depth-first order
+         */
+        self.leaves().flat_map(|leaf| self.node_indices(leaf).iter().copied().zip(self.node_points(leaf)))
+    }
+
+    pub fn query_range(&self, rect: &Rectangle, out: &mut Vec<u32>) {
+        /*
+        Appends the particle index of every point inside rect (edges included) to out.
+        Subtrees that miss rect are skipped and subtrees fully inside it are taken whole
+         */
+        let mut i: usize = 0;
+        while i < self.nodes.len() {
+            let node: &Node = &self.nodes[i];
+            if node.start == node.end || !node.area.intersects(rect) {
+                i = node.next as usize;
+                continue;
+            }
+            if rect.contains_rect(&node.area) {
+                out.extend_from_slice(self.node_indices(node));
+                i = node.next as usize;
+                continue;
+            }
+            if node.is_leaf() {
+                for (q, &id) in self.node_points(node).zip(self.node_indices(node)) {
+                    if rect.contains(&q) {
+                        out.push(id);
+                    }
+                }
+                i = node.next as usize;
+                continue;
+            }
+            i += 1;
         }
+    }
 
-        let size = self.area.size();
-        if depth >= MAX_DEPTH || size.x <= MIN_SIZE || size.y <= MIN_SIZE {
-            // avoid stack overflow
-            self.elements.push(p);
-            return;
+    pub fn query_radius(&self, center: Point, radius: Real, out: &mut Vec<u32>) {
+        /*
+        Appends the particle index of every point within radius of center to out
+         */
+        let r2: Real = radius * radius;
+        let mut i: usize = 0;
+        while i < self.nodes.len() {
+            let node: &Node = &self.nodes[i];
+            if node.start == node.end || node.area.distance_to_point(&center) > r2 {
+                i = node.next as usize;
+                continue;
+            }
+            // furthest corner inside the circle means the whole node is
+            let fx: Real = (center.x - node.area.p1.x).abs().max((node.area.p2.x - center.x).abs());
+            let fy: Real = (center.y - node.area.p1.y).abs().max((node.area.p2.y - center.y).abs());
+            if fx * fx + fy * fy <= r2 {
+                out.extend_from_slice(self.node_indices(node));
+                i = node.next as usize;
+                continue;
+            }
+            if node.is_leaf() {
+                for (q, &id) in self.node_points(node).zip(self.node_indices(node)) {
+                    let dx: Real = q.x - center.x;
+                    let dy: Real = q.y - center.y;
+                    if dx * dx + dy * dy <= r2 {
+                        out.push(id);
+                    }
+                }
+                i = node.next as usize;
+                continue;
+            }
+            i += 1;
         }
+    }
 
-        if self.is_leaf() && self.elements.len() < self.threshold {
-            self.elements.push(p);
+    pub fn nearest(&self, p: Point, k: usize, out: &mut Vec<u32>) {
+        /*
+        Appends the particle indices of the k points closest to p to out, nearest first
+        (equal distances are ordered by index). Best-first search: nodes are visited in order
+        of their distance to p, and the search stops once the closest remaining node is
+        further away than the k-th best point found so far
+         */
+        if k == 0 || self.nodes.is_empty() {
             return;
         }
+        let mut queue: BinaryHeap<Reverse<(Dist, u32)>> = BinaryHeap::new();
+        let mut best: BinaryHeap<(Dist, u32)> = BinaryHeap::with_capacity(k + 1);
+        queue.push(Reverse((Dist(self.nodes[0].area.distance_to_point(&p)), 0)));
 
-        if self.is_leaf() {
-            self.subdivide(); // creates 4 children
-            // reinsert existing points into children
-            let old = std::mem::take(&mut self.elements);
-            for q in old {
-                self.insert_depth(q, depth + 1);
+        while let Some(Reverse((d, i))) = queue.pop() {
+            if best.len() == k && best.peek().is_some_and(|top| d.0 > top.0.0) {
+                break;
+            }
+            let node: &Node = &self.nodes[i as usize];
+            if node.start == node.end {
+                continue;
+            }
+            if node.is_leaf() {
+                for (q, &id) in self.node_points(node).zip(self.node_indices(node)) {
+                    let dx: Real = q.x - p.x;
+                    let dy: Real = q.y - p.y;
+                    best.push((Dist(dx * dx + dy * dy), id));
+                    if best.len() > k {
+                        best.pop();
+                    }
+                }
+                continue;
+            }
+            for &z in node.zones.iter().flatten() {
+                queue.push(Reverse((Dist(self.nodes[z as usize].area.distance_to_point(&p)), z)));
             }
         }
+        out.extend(best.into_sorted_vec().into_iter().map(|(_, id)| id));
+    }
 
-        // insert new point into a child
-        for child in self.zones.iter_mut().filter_map(|z| z.as_deref_mut()) {
-            if child.area.contains(&p) {
-                child.insert_depth(p, depth + 1);
-                return;
+    pub fn rebuild(&mut self, particles: &[Particle], bounds: Rectangle) {
+        /*
+        Rebuilds the tree in place, keeping the node and point buffers from the last build.
+        Particles outside of bounds are left out
+         */
+        self.area = bounds;
+        self.nodes.clear();
+        self.points.clear();
+        self.index.clear();
+        self.keys.clear();
+        for (i, p) in particles.iter().enumerate() {
+            if bounds.contains(&p.position) {
+                self.points.push(p.position);
+                self.index.push(i as u32);
+            }
+        }
+
+        let levels: u32 = if self.parallel { PARALLEL_LEVELS } else { 0 };
+        build_node(&mut self.nodes, &mut self.points, &mut self.index, &[], bounds, 0, 0, self.threshold, levels);
+        self.finish_build(particles);
+    }
+
+    pub fn rebuild_morton(&mut self, particles: &[Particle], bounds: Rectangle) {
+        /*
+        Same tree as rebuild, but built from Morton (Z-curve) keys: the points are radix
+        sorted by key, after which every node's children are found by binary searching
+        the key range for the quadrant digit at that depth instead of moving points around.
+        The result does not depend on the order of the particles
+         */
+        self.area = bounds;
+        self.nodes.clear();
+        self.points.clear();
+        self.index.clear();
+        self.keys.clear();
+        self.keyed.clear();
+        for (i, p) in particles.iter().enumerate() {
+            if bounds.contains(&p.position) {
+                self.keyed.push((morton_key(&p.position, &bounds), (p.position, i as u32)));
             }
         }
+        radix_sort(&mut self.keyed, &mut self.scratch);
+        for &(key, (p, i)) in &self.keyed {
+            self.keys.push(key);
+            self.points.push(p);
+            self.index.push(i);
+        }
 
-        // fallback: if point lies exactly on a boundary, keep it here
-        self.elements.push(p);
+        let levels: u32 = if self.parallel { PARALLEL_LEVELS } else { 0 };
+        build_node(&mut self.nodes, &mut self.points, &mut self.index, &self.keys, bounds, 0, 0, self.threshold, levels);
+        self.finish_build(particles);
     }
 
-    fn subdivide(&mut self) {
+    fn finish_build(&mut self, particles: &[Particle]) {
         /*
-        Divides the quadtree into its respective 4 quadtrants
+        what both builds do once the nodes are in place: copy the element positions, masses
+        and charges into tree order and sum up the moments
          */
-        let p1: Point = self.area.p1;
-        let p2: Point = self.area.p2;
-        let mid: Point = Point { x: (p1.x + p2.x) * 0.5, y: (p1.y + p2.y) * 0.5 };
-
-        // NW, NE, SW, SE
-        let nw: Rectangle = Rectangle { p1: Point { x: p1.x, y: mid.y }, p2: Point { x: mid.x, y: p2.y } };
-        let ne: Rectangle = Rectangle { p1: Point { x: mid.x, y: mid.y }, p2: Point { x: p2.x, y: p2.y } };
-        let sw: Rectangle = Rectangle { p1: Point { x: p1.x, y: p1.y }, p2: Point { x: mid.x, y: mid.y } };
-        let se: Rectangle = Rectangle { p1: Point { x: mid.x, y: p1.y }, p2: Point { x: p2.x, y: mid.y } };
-
-        self.zones = [
-            Some(Box::new(QuadTree::new(nw))),
-            Some(Box::new(QuadTree::new(ne))),
-            Some(Box::new(QuadTree::new(sw))),
-            Some(Box::new(QuadTree::new(se))),
-        ];
+        self.split_points();
+        self.masses.clear();
+        self.masses.extend(self.index.iter().map(|&i| particles[i as usize].mass));
+        self.charges.clear();
+        self.charges.extend(self.index.iter().map(|&i| particles[i as usize].charge));
+        self.compute_moments();
+    }
+
+    fn split_points(&mut self) {
+        self.xs.clear();
+        self.ys.clear();
+        self.xs.extend(self.points.iter().map(|p| p.x));
+        self.ys.extend(self.points.iter().map(|p| p.y));
+    }
+
+    pub fn compute_moments(&mut self) {
+        /*
+        Bottom-up pass that fills in mass, center of mass and the quadrupole tensor
+        for every node, and the charge moments. Children always come after their parent,
+        so walking the array backwards visits every child before its parent.
+        Elements are weighted by their particle's mass
+         */
+        for i in (0..self.nodes.len()).rev() {
+            let node: Node = self.nodes[i];
+            let mut mass: Real = 0.0;
+            let mut weighted: Point = Point::zero();
+            let mut quad: [Real; 3] = [0.0; 3];
+
+            if node.is_leaf() {
+                let masses: &[Real] = &self.masses[node.start as usize..node.end as usize];
+                for (q, &m) in self.node_points(&node).zip(masses) {
+                    mass += m;
+                    weighted = weighted.add(&q.mult_scalar(m));
+                }
+                if mass > 0.0 {
+                    let cm: Point = weighted.div(mass);
+                    // Q_ij = sum m * (3 s_i s_j - |s|^2 delta_ij), s measured from this node's cm
+                    for (q, &m) in self.node_points(&node).zip(masses) {
+                        add_quadrupole(&mut quad, m, q.x - cm.x, q.y - cm.y);
+                    }
+                }
+            } else {
+                for c in node.zones.iter().flatten() {
+                    let child: &Node = &self.nodes[*c as usize];
+                    mass += child.mass;
+                    weighted = weighted.add(&child.cm.mult_scalar(child.mass));
+                }
+                if mass > 0.0 {
+                    let cm: Point = weighted.div(mass);
+                    // parallel axis theorem to shift each child's tensor onto our cm
+                    for c in node.zones.iter().flatten() {
+                        let child: &Node = &self.nodes[*c as usize];
+                        if child.mass == 0.0 {
+                            continue;
+                        }
+                        quad[0] += child.quad[0];
+                        quad[1] += child.quad[1];
+                        quad[2] += child.quad[2];
+                        add_quadrupole(&mut quad, child.mass, child.cm.x - cm.x, child.cm.y - cm.y);
+                    }
+                }
+            }
+
+            let (charge, abs_charge, qcenter, dipole) = self.charge_moments(&node);
+            let out: &mut Node = &mut self.nodes[i];
+            out.mass = mass;
+            out.cm = if mass > 0.0 { weighted.div(mass) } else { Point::zero() };
+            out.quad = quad;
+            out.charge = charge;
+            out.abs_charge = abs_charge;
+            out.qcenter = qcenter;
+            out.dipole = dipole;
+        }
+    }
+
+    fn charge_moments(&self, node: &Node) -> (Real, Real, Point, Point) {
+        /*
+        (charge, abs_charge, qcenter, dipole) of node, from its elements for a leaf
+        and from its children's moments otherwise
+         */
+        let (mut charge, mut abs_charge): (Real, Real) = (0.0, 0.0);
+        let mut weighted: Point = Point::zero();
+        if node.is_leaf() {
+            let charges: &[Real] = self.charges.get(node.start as usize..node.end as usize).unwrap_or(&[]);
+            for (q, &c) in self.node_points(node).zip(charges) {
+                charge += c;
+                abs_charge += c.abs();
+                weighted = weighted.add(&q.mult_scalar(c.abs()));
+            }
+            if abs_charge == 0.0 {
+                return (0.0, 0.0, Point::zero(), Point::zero());
+            }
+            let qcenter: Point = weighted.div(abs_charge);
+            let mut dipole: Point = Point::zero();
+            for (q, &c) in self.node_points(node).zip(charges) {
+                dipole.x += c * (q.x - qcenter.x);
+                dipole.y += c * (q.y - qcenter.y);
+            }
+            return (charge, abs_charge, qcenter, dipole);
+        }
+        for child in self.children(node) {
+            charge += child.charge;
+            abs_charge += child.abs_charge;
+            weighted = weighted.add(&child.qcenter.mult_scalar(child.abs_charge));
+        }
+        if abs_charge == 0.0 {
+            return (0.0, 0.0, Point::zero(), Point::zero());
+        }
+        let qcenter: Point = weighted.div(abs_charge);
+        let mut dipole: Point = Point::zero();
+        for child in self.children(node) {
+            // shift each child's dipole onto our center
+            dipole.x += child.dipole.x + child.charge * (child.qcenter.x - qcenter.x);
+            dipole.y += child.dipole.y + child.charge * (child.qcenter.y - qcenter.y);
+        }
+        (charge, abs_charge, qcenter, dipole)
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Dist(Real); // squared distance with a total order, for the nearest neighbor heaps
+
+impl Eq for Dist {}
+
+impl PartialOrd for Dist {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Dist {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+// quadrant number, points, particle ids and Morton keys of one child during a build
+type Child<'a> = (usize, &'a mut [Point], &'a mut [u32], &'a [u64]);
+
+#[allow(clippy::too_many_arguments)]
+fn build_node(
+    nodes: &mut Vec<Node>,
+    points: &mut [Point],
+    ids: &mut [u32],
+    keys: &[u64],
+    area: Rectangle,
+    start: u32,
+    depth: u32,
+    threshold: usize,
+    parallel_levels: u32,
+) -> u32 {
+    /*
+    Creates the node for points (which sit at index[start..] in the tree) and splits them
+    into the 4 quadrants, children are pushed right after their parent (depth first).
+    ids are the points' particle indices and are moved along with them.
+    keys is empty for a plain build, or holds the Morton keys of points, in which case
+    the points are already sorted and the split can be read off the keys.
+    While parallel_levels > 0 the 4 children are built into their own arrays on the thread pool
+    and then appended in order, which gives exactly the same layout as the serial build
+     */
+    let index: u32 = nodes.len() as u32;
+    let end: u32 = start + points.len() as u32;
+    nodes.push(Node {
+        area,
+        zones: [None; 4],
+        next: index + 1,
+        start,
+        end,
+        mass: 0.0,
+        cm: Point::zero(),
+        quad: [0.0; 3],
+        charge: 0.0,
+        abs_charge: 0.0,
+        qcenter: Point::zero(),
+        dipole: Point::zero(),
+    });
+
+    let size: Point = area.size();
+    if points.len() <= threshold || depth >= MAX_DEPTH || size.x <= MIN_SIZE || size.y <= MIN_SIZE {
+        // leaf (or capped, to avoid runaway subdivision of coincident points)
+        return index;
+    }
+
+    let mut bounds: [usize; 5] = [0, 0, 0, 0, points.len()];
+    if !keys.is_empty() {
+        for (k, bound) in bounds.iter_mut().enumerate().take(4).skip(1) {
+            *bound = keys.partition_point(|&key| quadrant_at(key, depth) < k as u64);
+        }
+    } else {
+        let mid: Point = area.center();
+        // points on a split line go to the north / west side, same as Rectangle::contains order
+        let south: usize = partition(points, ids, |q| q.y >= mid.y);
+        bounds[1] = partition(&mut points[..south], &mut ids[..south], |q| q.x <= mid.x);
+        bounds[2] = south;
+        bounds[3] = south + partition(&mut points[south..], &mut ids[south..], |q| q.x <= mid.x);
+    }
+
+    let (nw, rest) = points.split_at_mut(bounds[1]);
+    let (ne, rest) = rest.split_at_mut(bounds[2] - bounds[1]);
+    let (sw, se) = rest.split_at_mut(bounds[3] - bounds[2]);
+    let (nw_ids, rest) = ids.split_at_mut(bounds[1]);
+    let (ne_ids, rest) = rest.split_at_mut(bounds[2] - bounds[1]);
+    let (sw_ids, se_ids) = rest.split_at_mut(bounds[3] - bounds[2]);
+    let quadrants: [Rectangle; 4] = area.quadrants();
+    let children: Vec<Child> = [nw, ne, sw, se]
+        .into_iter()
+        .zip([nw_ids, ne_ids, sw_ids, se_ids])
+        .enumerate()
+        .map(|(k, (pts, child_ids))| {
+            let child_keys: &[u64] = if keys.is_empty() { &[] } else { &keys[bounds[k]..bounds[k + 1]] };
+            (k, pts, child_ids, child_keys)
+        })
+        .collect();
+
+    if parallel_levels > 0 {
+        let parts: Vec<(usize, Vec<Node>)> = children
+            .into_par_iter()
+            .map(|(k, pts, child_ids, child_keys)| {
+                let mut sub: Vec<Node> = Vec::new();
+                let child_start: u32 = start + bounds[k] as u32;
+                build_node(&mut sub, pts, child_ids, child_keys, quadrants[k], child_start, depth + 1, threshold, parallel_levels - 1);
+                (k, sub)
+            })
+            .collect();
+
+        for (k, sub) in parts {
+            // indices inside sub start at 0, shift them to where sub lands in nodes
+            let offset: u32 = nodes.len() as u32;
+            nodes[index as usize].zones[k] = Some(offset);
+            nodes.extend(sub.into_iter().map(|mut node| {
+                node.next += offset;
+                for z in node.zones.iter_mut().flatten() {
+                    *z += offset;
+                }
+                node
+            }));
+        }
+    } else {
+        for (k, pts, child_ids, child_keys) in children {
+            let child_start: u32 = start + bounds[k] as u32;
+            let child: u32 = build_node(nodes, pts, child_ids, child_keys, quadrants[k], child_start, depth + 1, threshold, 0);
+            nodes[index as usize].zones[k] = Some(child);
+        }
+    }
+    nodes[index as usize].next = nodes.len() as u32;
+    index
+}
+
+fn partition(points: &mut [Point], ids: &mut [u32], pred: impl Fn(&Point) -> bool) -> usize {
+    /*
+    moves every point matching pred (and its id) to the front, returns how many matched
+     */
+    let mut split: usize = 0;
+    for i in 0..points.len() {
+        if pred(&points[i]) {
+            points.swap(split, i);
+            ids.swap(split, i);
+            split += 1;
+        }
+    }
+    split
+}
+
+fn add_quadrupole(quad: &mut [Real; 3], mass: Real, sx: Real, sy: Real) {
+    let s2: Real = sx * sx + sy * sy;
+    quad[0] += mass * (3.0 * sx * sx - s2);
+    quad[1] += mass * 3.0 * sx * sy;
+    quad[2] += mass * (3.0 * sy * sy - s2);
+}
+
 pub fn build_tree(particles: &[Particle], bounds: Rectangle) -> QuadTree {
     /*
-    insert the particles into the quadtree with the appropriate world bounds
+    insert the particles into a new quadtree with the appropriate world bounds,
+    use QuadTree::rebuild to reuse an existing tree's buffers instead
      */
     let mut qt: QuadTree = QuadTree::new(bounds);
-    for p in particles {
-        qt.insert(p.position);
-    }
+    qt.rebuild(particles, bounds);
     qt
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    fn scatter(n: usize) -> Vec<Particle> {
+        let mut seed: u32 = 987654321;
+        let mut next_f32 = || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            (seed >> 8) as Real / (1u32 << 24) as Real
+        };
+        (0..n)
+            .map(|i| {
+                let position: Point = Point::new(-100.0 + 200.0 * next_f32(), -75.0 + 150.0 * next_f32());
+                Particle::new(i as u64, position, Vector { x: 0.0, y: 0.0 }, 1.0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_morton_build_matches_partition_build() {
+        let bounds: Rectangle = Rectangle::new(Point::new(-100.0, -75.0), Point::new(100.0, 75.0));
+        let mut particles: Vec<Particle> = scatter(2000);
+        let a: QuadTree = build_tree(&particles, bounds);
+
+        particles.reverse();
+        let mut b: QuadTree = QuadTree::new(bounds);
+        b.rebuild_morton(&particles, bounds);
+
+        assert_eq!(a.nodes.len(), b.nodes.len());
+        for (na, nb) in a.nodes.iter().zip(b.nodes.iter()) {
+            assert_eq!(na.zones, nb.zones);
+            assert_eq!(na.end - na.start, nb.end - nb.start);
+            assert!(na.cm.distance(&nb.cm) < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_parallel_build_matches_serial_build() {
+        let bounds: Rectangle = Rectangle::new(Point::new(-100.0, -75.0), Point::new(100.0, 75.0));
+        let particles: Vec<Particle> = scatter(5000);
+        let serial: QuadTree = build_tree(&particles, bounds);
+
+        let mut parallel: QuadTree = QuadTree::new(bounds);
+        parallel.parallel = true;
+        parallel.rebuild(&particles, bounds);
+
+        assert_eq!(serial.nodes.len(), parallel.nodes.len());
+        for (a, b) in serial.nodes.iter().zip(parallel.nodes.iter()) {
+            assert_eq!(a.zones, b.zones);
+            assert_eq!((a.start, a.end, a.next), (b.start, b.end, b.next));
+            assert_eq!(a.mass, b.mass);
+            assert_eq!((a.cm.x, a.cm.y), (b.cm.x, b.cm.y));
+        }
+    }
+
+    fn random_points(rng: &mut Rng, bounds: Rectangle) -> Vec<Particle> {
+        /*
+        a random mix of the cases the build has to handle: scattered points, tight clumps,
+        exact duplicates, points on split lines and on the outer edge, and points outside
+         */
+        let n: usize = (rng.uniform() * 300.0) as usize;
+        let size: Point = bounds.size();
+        let mut points: Vec<Point> = Vec::with_capacity(n);
+        while points.len() < n {
+            let p: Point = match (rng.uniform() * 6.0) as u32 {
+                0 | 1 => Point::new(rng.range(bounds.p1.x, bounds.p2.x), rng.range(bounds.p1.y, bounds.p2.y)),
+                2 => {
+                    let c: Point = points.last().copied().unwrap_or(bounds.center());
+                    Point::new(c.x + 1e-3 * rng.normal(), c.y + 1e-3 * rng.normal())
+                }
+                3 => points.last().copied().unwrap_or(bounds.p1),
+                4 => {
+                    // corner of a grid cell a few levels down
+                    let cells: Real = (1u32 << (rng.uniform() * 5.0) as u32) as Real;
+                    let gx: Real = (rng.uniform() * (cells + 1.0)).floor();
+                    let gy: Real = (rng.uniform() * (cells + 1.0)).floor();
+                    Point::new(bounds.p1.x + size.x * gx / cells, bounds.p1.y + size.y * gy / cells)
+                }
+                _ => Point::new(rng.range(-2.0, 2.0) * size.x, rng.range(-2.0, 2.0) * size.y),
+            };
+            points.push(p);
+        }
+        points
+            .into_iter()
+            .enumerate()
+            .map(|(i, position)| Particle::new(i as u64, position, Vector { x: 0.0, y: 0.0 }, 1.0))
+            .collect()
+    }
+
+    fn check_invariants(tree: &QuadTree, particles: &[Particle], case: u64) {
+        // every in-bounds point is in the tree exactly once
+        let key = |p: &Point| (p.x.to_bits(), p.y.to_bits());
+        let mut expected: Vec<_> = particles.iter().map(|p| p.position).filter(|p| tree.area.contains(p)).map(|p| key(&p)).collect();
+        let mut found: Vec<_> = tree.iter().map(|(_, p)| key(&p)).collect();
+        expected.sort_unstable();
+        found.sort_unstable();
+        assert_eq!(expected, found, "case {case}: points lost or duplicated");
+        for (i, p) in tree.iter() {
+            assert_eq!(key(&p), key(&particles[i as usize].position), "case {case}: element index points at another particle");
+        }
+        if let Some(root) = tree.nodes.first() {
+            let walked: Vec<_> = tree.node_particles(root, particles).map(|p| key(&p.position)).collect();
+            assert_eq!(walked, tree.node_points(root).map(|p| key(&p)).collect::<Vec<_>>());
+        }
+
+        // walk from the root to know every node's depth
+        let mut depth: Vec<u32> = vec![0; tree.nodes.len()];
+        for (i, node) in tree.nodes.iter().enumerate() {
+            for &z in node.zones.iter().flatten() {
+                depth[z as usize] = depth[i] + 1;
+            }
+        }
+
+        for (i, node) in tree.nodes.iter().enumerate() {
+            let points: Vec<Point> = tree.node_points(node).collect();
+            for p in &points {
+                assert!(node.area.contains(p), "case {case}: node {i} holds {p:?} outside {:?}", node.area);
+            }
+
+            if node.is_leaf() {
+                let size: Point = node.area.size();
+                let capped: bool = depth[i] >= MAX_DEPTH || size.x <= MIN_SIZE || size.y <= MIN_SIZE;
+                assert!(points.len() <= tree.threshold || capped, "case {case}: leaf {i} over threshold");
+            } else {
+                // children tile the parent: they are its quadrants and their ranges cover its range
+                let mut at: u32 = node.start;
+                for (k, z) in node.zones.iter().enumerate() {
+                    let child: &Node = &tree.nodes[z.expect("internal nodes have all 4 children") as usize];
+                    let q: Rectangle = node.area.quadrants()[k];
+                    assert_eq!((child.area.p1.x, child.area.p1.y, child.area.p2.x, child.area.p2.y), (q.p1.x, q.p1.y, q.p2.x, q.p2.y));
+                    assert_eq!(child.start, at, "case {case}: child ranges of node {i} do not line up");
+                    at = child.end;
+                }
+                assert_eq!(at, node.end);
+            }
+
+            // moments against brute force over the node's points
+            let n: f64 = points.len() as f64;
+            assert_eq!(node.mass as f64, n, "case {case}: node {i} mass");
+            if n > 0.0 {
+                let cx: f64 = points.iter().map(|p| p.x as f64).sum::<f64>() / n;
+                let cy: f64 = points.iter().map(|p| p.y as f64).sum::<f64>() / n;
+                // summing n coordinates in Real loses up to ~n ulps of the largest coordinate
+                let big: f64 = points.iter().map(|p| p.x.abs().max(p.y.abs()) as f64).fold(0.0, f64::max);
+                let tol: f64 = n * big * Real::EPSILON as f64 + 1e-9;
+                assert!((node.cm.x as f64 - cx).abs() <= tol, "case {case}: node {i} cm.x {} vs {cx}", node.cm.x);
+                assert!((node.cm.y as f64 - cy).abs() <= tol, "case {case}: node {i} cm.y {} vs {cy}", node.cm.y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tree_invariants_random_point_sets() {
+        // the case number is the seed, so a failing case can be replayed on its own
+        let bounds: Rectangle = Rectangle::new(Point::new(-100.0, -75.0), Point::new(100.0, 75.0));
+        let mut tree: QuadTree = QuadTree::new(bounds);
+        for case in 0..1000u64 {
+            let mut rng: Rng = Rng::new(case);
+            let particles: Vec<Particle> = random_points(&mut rng, bounds);
+            tree.parallel = case % 2 == 1;
+            if case % 3 == 0 {
+                tree.rebuild_morton(&particles, bounds);
+            } else {
+                tree.rebuild(&particles, bounds);
+            }
+            check_invariants(&tree, &particles, case);
+        }
+    }
+
+    #[test]
+    fn test_rectangle_intersects() {
+        let r: Rectangle = Rectangle::new(Point::new(0.0, 0.0), Point::new(10.0, 10.0));
+        let rect = |x1: Real, y1: Real, x2: Real, y2: Real| Rectangle::new(Point::new(x1, y1), Point::new(x2, y2));
+        assert!(r.intersects(&rect(5.0, 5.0, 15.0, 15.0)));
+        assert!(r.intersects(&rect(-5.0, -5.0, 5.0, 5.0))); // overlaps through its p2 corner
+        assert!(r.intersects(&rect(-5.0, 2.0, 15.0, 3.0))); // crosses without a corner inside
+        assert!(r.intersects(&rect(2.0, 2.0, 3.0, 3.0)));
+        assert!(rect(2.0, 2.0, 3.0, 3.0).intersects(&r));
+        assert!(r.intersects(&rect(10.0, 10.0, 12.0, 12.0))); // touching corner
+        assert!(!r.intersects(&rect(11.0, 0.0, 12.0, 10.0)));
+        assert!(!r.intersects(&rect(0.0, -3.0, 10.0, -1.0)));
+    }
+
+    #[test]
+    fn test_queries_match_brute_force() {
+        let bounds: Rectangle = Rectangle::new(Point::new(-100.0, -75.0), Point::new(100.0, 75.0));
+        let mut rng: Rng = Rng::new(11);
+        let mut particles: Vec<Particle> = scatter(3000);
+        // duplicates and a few points outside the bounds, which the tree leaves out
+        particles.extend_from_within(..50);
+        particles[7].position = Point::new(150.0, 0.0);
+        let inside: Vec<usize> = (0..particles.len()).filter(|&i| bounds.contains(&particles[i].position)).collect();
+
+        let mut tree: QuadTree = QuadTree::new(bounds);
+        let mut out: Vec<u32> = Vec::new();
+        for case in 0..200 {
+            if case % 2 == 0 {
+                tree.rebuild(&particles, bounds);
+            } else {
+                tree.rebuild_morton(&particles, bounds);
+            }
+            let c: Point = Point::new(rng.range(-120.0, 120.0), rng.range(-90.0, 90.0));
+
+            let rect: Rectangle = Rectangle::new(c, Point::new(c.x + rng.range(0.0, 80.0), c.y + rng.range(0.0, 60.0)));
+            out.clear();
+            tree.query_range(&rect, &mut out);
+            out.sort_unstable();
+            let expected: Vec<u32> = inside.iter().filter(|&&i| rect.contains(&particles[i].position)).map(|&i| i as u32).collect();
+            assert_eq!(out, expected, "range case {case}");
+
+            let radius: Real = rng.range(0.0, 40.0);
+            let d2 = |i: usize| {
+                let dx: Real = particles[i].position.x - c.x;
+                let dy: Real = particles[i].position.y - c.y;
+                dx * dx + dy * dy
+            };
+            out.clear();
+            tree.query_radius(c, radius, &mut out);
+            out.sort_unstable();
+            let expected: Vec<u32> = inside.iter().filter(|&&i| d2(i) <= radius * radius).map(|&i| i as u32).collect();
+            assert_eq!(out, expected, "radius case {case}");
+
+            let k: usize = (rng.uniform() * 20.0) as usize;
+            out.clear();
+            tree.nearest(c, k, &mut out);
+            let mut by_dist: Vec<usize> = inside.clone();
+            by_dist.sort_by(|&a, &b| d2(a).total_cmp(&d2(b)).then(a.cmp(&b)));
+            let expected: Vec<u32> = by_dist[..k].iter().map(|&i| i as u32).collect();
+            assert_eq!(out, expected, "nearest case {case}");
+        }
+    }
+}
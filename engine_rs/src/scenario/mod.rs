@@ -0,0 +1,145 @@
+use crate::Real;
+use crate::attractor::Attractor;
+use crate::constraint::{Constraints, Pin, Rod, Spring};
+use crate::quadtree::{Point, Rectangle};
+use crate::rng::Rng;
+use crate::vector::{Particle, Vector};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Distribution {
+    /*
+    Initial particle layouts used by the viewer and the benchmarks
+     */
+    Uniform,   // evenly spread over the bounds
+    Clustered, // a handful of tight gaussian clumps
+    Disk,      // a disk around the center, denser toward the middle
+}
+
+impl Distribution {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Distribution::Uniform => "uniform",
+            Distribution::Clustered => "clustered",
+            Distribution::Disk => "disk",
+        }
+    }
+}
+
+pub fn circular_velocity(p: Point, center: Point, gm: Real, eps2: Real) -> Vector {
+    /*
+    velocity for a circular orbit around a softened point mass at center
+     */
+    let dx: Real = p.x - center.x;
+    let dy: Real = p.y - center.y;
+    let r2: Real = dx * dx + dy * dy + eps2;
+    let r: Real = r2.sqrt();
+
+    // circular orbit speed
+    let v: Real = (gm / r).sqrt();
+
+    // perpendicular direction gives orbit
+    Vector { x: -dy / r * v, y: dx / r * v }
+}
+
+pub fn generate(dist: Distribution, n: usize, bounds: Rectangle, seed: u64) -> Vec<Particle> {
+    /*
+    generate_around the viewer's central mass
+     */
+    generate_around(dist, n, bounds, seed, &[Attractor::central()])
+}
+
+pub fn generate_around(dist: Distribution, n: usize, bounds: Rectangle, seed: u64, attractors: &[Attractor]) -> Vec<Particle> {
+    /*
+    n unit mass particles laid out by dist inside bounds, each on a circular orbit around the
+    attractors as they are at t = 0, seen as one mass at their barycenter (at rest without attractors).
+    Ids are 0..n, clustered particles are tagged with the index of their clump
+     */
+    let gm: Real = attractors.iter().map(|a| a.gm).sum();
+    let (mut bary, mut eps2): (Point, Real) = (Point::zero(), 0.0);
+    for a in attractors {
+        bary = bary.add(&a.position_at(0.0).mult_scalar(a.gm / gm));
+        eps2 += a.eps2 * a.gm / gm;
+    }
+
+    let mut rng: Rng = Rng::new(seed);
+    let center: Point = bounds.center();
+    let size: Point = bounds.size();
+
+    // clump centers for the clustered layout, kept away from the edges
+    let clumps: Vec<Point> = (0..8)
+        .map(|_| Point {
+            x: bounds.p1.x + size.x * (0.15 + 0.7 * rng.uniform()),
+            y: bounds.p1.y + size.y * (0.15 + 0.7 * rng.uniform()),
+        })
+        .collect();
+    let sigma: Real = 0.03 * size.x.min(size.y);
+    let radius: Real = 0.45 * size.x.min(size.y);
+
+    let sample = |rng: &mut Rng| -> (Point, Option<u32>) {
+        match dist {
+            Distribution::Uniform => {
+                let p: Point = Point {
+                    x: bounds.p1.x + size.x * rng.uniform(),
+                    y: bounds.p1.y + size.y * rng.uniform(),
+                };
+                (p, None)
+            }
+            Distribution::Clustered => {
+                let k: usize = (rng.uniform() * clumps.len() as Real) as usize;
+                let c: Point = clumps[k];
+                (Point { x: c.x + sigma * rng.normal(), y: c.y + sigma * rng.normal() }, Some(k as u32))
+            }
+            Distribution::Disk => {
+                // linear radius gives surface density falling off as 1/r
+                let r: Real = radius * rng.uniform();
+                let t: Real = 2.0 * std::f64::consts::PI as Real * rng.uniform();
+                (Point { x: center.x + r * t.cos(), y: center.y + r * t.sin() }, None)
+            }
+        }
+    };
+
+    let mut particles: Vec<Particle> = Vec::with_capacity(n);
+    while particles.len() < n {
+        let (p, tag) = sample(&mut rng);
+        if !bounds.contains(&p) {
+            continue;
+        }
+        let velocity: Vector = if gm > 0.0 { circular_velocity(p, bary, gm, eps2) } else { Vector { x: 0.0, y: 0.0 } };
+        particles.push(Particle { tag, ..Particle::new(particles.len() as u64, p, velocity, 1.0) });
+    }
+    particles
+}
+
+pub fn cloth(top_left: Point, cols: usize, rows: usize, spacing: Real) -> (Vec<Particle>, Constraints) {
+    /*
+    A cols x rows sheet of unit mass particles hanging from its two top corners: rods between
+    horizontal and vertical neighbors, and soft springs across the diagonals against shearing.
+    A tall sheet needs more projection passes than the default to keep from stretching.
+    Ids are row * cols + col
+     */
+    let id = |col: usize, row: usize| (row * cols + col) as u64;
+    let mut particles: Vec<Particle> = Vec::with_capacity(cols * rows);
+    let mut set: Constraints = Constraints { iterations: 20, ..Constraints::default() };
+    let diagonal: Real = spacing * std::f64::consts::SQRT_2 as Real;
+    for row in 0..rows {
+        for col in 0..cols {
+            let position: Point = Point { x: top_left.x + col as Real * spacing, y: top_left.y - row as Real * spacing };
+            particles.push(Particle::new(id(col, row), position, Vector { x: 0.0, y: 0.0 }, 1.0));
+            if col + 1 < cols {
+                set.rods.push(Rod { a: id(col, row), b: id(col + 1, row), length: spacing });
+            }
+            if row + 1 < rows {
+                set.rods.push(Rod { a: id(col, row), b: id(col, row + 1), length: spacing });
+            }
+            if col + 1 < cols && row + 1 < rows {
+                for (a, b) in [(id(col, row), id(col + 1, row + 1)), (id(col + 1, row), id(col, row + 1))] {
+                    set.springs.push(Spring { a, b, rest: diagonal, stiffness: 20.0, damping: 0.5 });
+                }
+            }
+        }
+    }
+    for col in [0, cols - 1] {
+        set.pins.push(Pin { particle: id(col, 0), at: particles[col].position });
+    }
+    (particles, set)
+}
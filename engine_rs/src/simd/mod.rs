@@ -0,0 +1,501 @@
+use crate::Real;
+use crate::barnes_hut::{Multipole, TreeParams, accept_node};
+use crate::quadtree::{Node, Point, QuadTree};
+use crate::softening::Softening;
+use crate::vector::Particle;
+
+/*
+Batched force kernels. std::simd is still nightly only, so the kernels work on fixed size
+[Real; LANES] blocks: whole-lane loops with no branches in them (selects instead, and the
+kernel chosen once outside), which LLVM turns into vector instructions on stable.
+eval_scalar and tree_force in barnes_hut stay the scalar paths
+ */
+pub const LANES: usize = 8;
+
+#[derive(Debug, Clone, Default)]
+pub struct ParticleSoa {
+    /*
+    Copy of the particle positions as a struct of arrays for the batched kernels, loaded before
+    every simd force pass. The entries are in the tree's order rather than the particles', so
+    the GROUP consecutive entries that share a walk are close together even if the particles
+    aren't sorted. Nothing is written back, the forces go back to the particles through id
+     */
+    pub x: Vec<Real>,
+    pub y: Vec<Real>,
+    pub a_old: Vec<Real>, // magnitude of the particle's acceleration from the last step
+    pub id: Vec<u32>,     // particle index of every entry
+}
+
+impl ParticleSoa {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.x.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.is_empty()
+    }
+
+    pub fn load(&mut self, particles: &[Particle], tree: &QuadTree) {
+        /*
+        Refills the arrays from particles in tree order (depth-first, Z order for both builds),
+        keeping their allocations. tree must have been built from particles, the ones outside
+        of it come last in index order
+         */
+        self.x.clear();
+        self.y.clear();
+        self.a_old.clear();
+        self.id.clear();
+        let outside = (0..particles.len() as u32).filter(|&i| !tree.area.contains(&particles[i as usize].position));
+        for i in tree.index.iter().copied().chain(outside) {
+            let p: &Particle = &particles[i as usize];
+            self.x.push(p.position.x);
+            self.y.push(p.position.y);
+            self.a_old.push((p.accel.x * p.accel.x + p.accel.y * p.accel.y).sqrt());
+            self.id.push(i);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InteractionList {
+    /*
+    Everything a group of particles interacts with, gathered by a tree walk:
+    the points of opened leaves and the accepted far-field nodes
+     */
+    pub px: Vec<Real>,
+    pub py: Vec<Real>,
+    pub pid: Vec<u32>, // particle index of each point
+    pub pmass: Vec<Real>, // mass of each point
+    pub peps2: Vec<Real>, // softening of each point, the adaptive value or params.eps2
+    pub nx: Vec<Real>,
+    pub ny: Vec<Real>,
+    pub mass: Vec<Real>,
+    pub qxx: Vec<Real>,
+    pub qxy: Vec<Real>,
+    pub qyy: Vec<Real>,
+}
+
+impl InteractionList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.px.clear();
+        self.py.clear();
+        self.pid.clear();
+        self.pmass.clear();
+        self.peps2.clear();
+        self.nx.clear();
+        self.ny.clear();
+        self.mass.clear();
+        self.qxx.clear();
+        self.qxy.clear();
+        self.qyy.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.px.len() + self.nx.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn push_node(&mut self, node: &Node) {
+        self.nx.push(node.cm.x);
+        self.ny.push(node.cm.y);
+        self.mass.push(node.mass);
+        self.qxx.push(node.quad[0]);
+        self.qxy.push(node.quad[1]);
+        self.qyy.push(node.quad[2]);
+    }
+}
+
+pub fn gather(
+    px: &[Real],
+    py: &[Real],
+    a_old: &[Real],
+    tree: &QuadTree,
+    soft: &[Real],
+    params: &TreeParams,
+    list: &mut InteractionList,
+) {
+    /*
+    Same walk as tree_force, but for a group of particles at once (px, py, a_old hold one entry
+    per particle). A node is only accepted if it is accepted for every particle in the group,
+    and instead of evaluating each interaction it is recorded in list. Particles that are close
+    together (Morton order) share most of their interactions, so one walk serves the whole group
+     */
+    list.clear();
+    let mut i: usize = 0;
+    while i < tree.nodes.len() {
+        let node: &Node = &tree.nodes[i];
+
+        if node.is_leaf() {
+            let (start, end) = (node.start as usize, node.end as usize);
+            list.px.extend_from_slice(&tree.xs[start..end]);
+            list.py.extend_from_slice(&tree.ys[start..end]);
+            list.pid.extend_from_slice(&tree.index[start..end]);
+            list.pmass.extend_from_slice(&tree.masses[start..end]);
+            if soft.is_empty() {
+                list.peps2.resize(list.pid.len(), params.eps2);
+            } else {
+                list.peps2.extend(tree.index[start..end].iter().map(|&id| soft[id as usize]));
+            }
+            i = node.next as usize;
+            continue;
+        }
+
+        if node.mass == 0.0 {
+            i = node.next as usize;
+            continue;
+        }
+
+        let accepted: bool = (0..px.len()).all(|k| accept_node(Point { x: px[k], y: py[k] }, node, params, a_old[k]));
+        if accepted {
+            list.push_node(node);
+            i = node.next as usize;
+            continue;
+        }
+
+        i += 1;
+    }
+}
+
+fn hsum(lanes: &[Real; LANES]) -> Real {
+    lanes.iter().sum()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn point_lane(px: Real, py: Real, qx: Real, qy: Real, m: Real, same: bool, g: Real, eps2: Real, kernel: Softening) -> (Real, Real) {
+    /*
+    inter_point_force for one lane, the particle itself (same) contributes nothing
+     */
+    let dx: Real = qx - px;
+    let dy: Real = qy - py;
+    let inv_r3: Real = kernel.inv_r3(dx * dx + dy * dy, eps2);
+    let k: Real = if same { 0.0 } else { g * m * inv_r3 };
+    (k * dx, k * dy)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn node_lane(px: Real, py: Real, cx: Real, cy: Real, m: Real, q: [Real; 3], g: Real, eps2: Real, kernel: Softening, quad: Real) -> (Real, Real) {
+    /*
+    force_point_to_mass plus, when quad is 1, the quadrupole correction for one lane
+     */
+    let dx: Real = cx - px;
+    let dy: Real = cy - py;
+    let inv_r3: Real = kernel.inv_r3(dx * dx + dy * dy, eps2);
+    // the quadrupole term keeps Plummer softening, the compact kernels are Newtonian that far out
+    let r2: Real = dx * dx + dy * dy + if kernel == Softening::Plummer { eps2 } else { 0.0 };
+    let inv_r5: Real = 1.0 / (r2 * r2.sqrt()) / r2;
+    let inv_r7: Real = inv_r5 / r2;
+
+    let qdx: Real = q[0] * dx + q[1] * dy;
+    let qdy: Real = q[1] * dx + q[2] * dy;
+    let dqd: Real = dx * qdx + dy * qdy;
+
+    let fx: Real = m * dx * inv_r3 + quad * (-qdx * inv_r5 + 2.5 * dqd * dx * inv_r7);
+    let fy: Real = m * dy * inv_r3 + quad * (-qdy * inv_r5 + 2.5 * dqd * dy * inv_r7);
+    (g * fx, g * fy)
+}
+
+pub fn eval_scalar(p: Point, me: u32, list: &InteractionList, params: &TreeParams) -> Point {
+    /*
+    evaluates list one interaction at a time for the particle with index me at p,
+    params.eps2 is that particle's own softening
+     */
+    let quad: Real = if params.order == Multipole::Quadrupole { 1.0 } else { 0.0 };
+    let (g, eps2, kernel) = (params.g, params.eps2, params.softening);
+    let mut fx: Real = 0.0;
+    let mut fy: Real = 0.0;
+    for k in 0..list.px.len() {
+        let (ax, ay) = point_lane(p.x, p.y, list.px[k], list.py[k], list.pmass[k], list.pid[k] == me, g, eps2.max(list.peps2[k]), kernel);
+        fx += ax;
+        fy += ay;
+    }
+    for k in 0..list.nx.len() {
+        let q: [Real; 3] = [list.qxx[k], list.qxy[k], list.qyy[k]];
+        let (ax, ay) = node_lane(p.x, p.y, list.nx[k], list.ny[k], list.mass[k], q, g, eps2, kernel, quad);
+        fx += ax;
+        fy += ay;
+    }
+    Point { x: fx, y: fy }
+}
+
+pub fn eval_simd(p: Point, me: u32, list: &InteractionList, params: &TreeParams) -> Point {
+    /*
+    evaluates list LANES interactions at a time. Each block runs as whole-lane loops with no
+    branches in them: the kernel and the multipole order are chosen once per block outside the
+    lane loops, the particle's own point is masked out with a select. The tail that doesn't fill
+    a block goes through the scalar per-lane functions
+     */
+    let (g, eps2, kernel) = (params.g, params.eps2, params.softening);
+    let mut ax: [Real; LANES] = [0.0; LANES];
+    let mut ay: [Real; LANES] = [0.0; LANES];
+    let (mut dx, mut dy, mut r2, mut e): ([Real; LANES], [Real; LANES], [Real; LANES], [Real; LANES]) =
+        ([0.0; LANES], [0.0; LANES], [0.0; LANES], [0.0; LANES]);
+
+    let points = list
+        .px
+        .chunks_exact(LANES)
+        .zip(list.py.chunks_exact(LANES))
+        .zip(list.pmass.chunks_exact(LANES))
+        .zip(list.pid.chunks_exact(LANES).zip(list.peps2.chunks_exact(LANES)));
+    for (((qx, qy), m), (id, pe)) in points {
+        for l in 0..LANES {
+            dx[l] = qx[l] - p.x;
+            dy[l] = qy[l] - p.y;
+            r2[l] = dx[l] * dx[l] + dy[l] * dy[l];
+            e[l] = eps2.max(pe[l]);
+        }
+        let inv_r3: [Real; LANES] = kernel.inv_r3_block(&r2, &e);
+        for l in 0..LANES {
+            let k: Real = g * m[l] * inv_r3[l];
+            let k: Real = if id[l] == me { 0.0 } else { k };
+            ax[l] += k * dx[l];
+            ay[l] += k * dy[l];
+        }
+    }
+    let tail: usize = list.px.len() - list.px.len() % LANES;
+    for k in tail..list.px.len() {
+        let (fx, fy) = point_lane(p.x, p.y, list.px[k], list.py[k], list.pmass[k], list.pid[k] == me, g, eps2.max(list.peps2[k]), kernel);
+        ax[k - tail] += fx;
+        ay[k - tail] += fy;
+    }
+
+    // the quadrupole term keeps Plummer softening, the compact kernels are Newtonian that far out
+    let quad_eps2: Real = if kernel == Softening::Plummer { eps2 } else { 0.0 };
+    let quadrupole: bool = params.order == Multipole::Quadrupole;
+    e = [eps2; LANES];
+    let nodes = list
+        .nx
+        .chunks_exact(LANES)
+        .zip(list.ny.chunks_exact(LANES))
+        .zip(list.mass.chunks_exact(LANES))
+        .zip(list.qxx.chunks_exact(LANES).zip(list.qxy.chunks_exact(LANES)).zip(list.qyy.chunks_exact(LANES)));
+    for (((cx, cy), m), ((qxx, qxy), qyy)) in nodes {
+        for l in 0..LANES {
+            dx[l] = cx[l] - p.x;
+            dy[l] = cy[l] - p.y;
+            r2[l] = dx[l] * dx[l] + dy[l] * dy[l];
+        }
+        let inv_r3: [Real; LANES] = kernel.inv_r3_block(&r2, &e);
+        if quadrupole {
+            for l in 0..LANES {
+                let s2: Real = r2[l] + quad_eps2;
+                let inv_r5: Real = 1.0 / (s2 * s2.sqrt()) / s2;
+                let inv_r7: Real = inv_r5 / s2;
+                let qdx: Real = qxx[l] * dx[l] + qxy[l] * dy[l];
+                let qdy: Real = qxy[l] * dx[l] + qyy[l] * dy[l];
+                let dqd: Real = dx[l] * qdx + dy[l] * qdy;
+                ax[l] += g * (m[l] * dx[l] * inv_r3[l] + (-qdx * inv_r5 + 2.5 * dqd * dx[l] * inv_r7));
+                ay[l] += g * (m[l] * dy[l] * inv_r3[l] + (-qdy * inv_r5 + 2.5 * dqd * dy[l] * inv_r7));
+            }
+        } else {
+            for l in 0..LANES {
+                ax[l] += g * (m[l] * dx[l] * inv_r3[l]);
+                ay[l] += g * (m[l] * dy[l] * inv_r3[l]);
+            }
+        }
+    }
+    let quad: Real = if quadrupole { 1.0 } else { 0.0 };
+    let tail: usize = list.nx.len() - list.nx.len() % LANES;
+    for k in tail..list.nx.len() {
+        let q: [Real; 3] = [list.qxx[k], list.qxy[k], list.qyy[k]];
+        let (fx, fy) = node_lane(p.x, p.y, list.nx[k], list.ny[k], list.mass[k], q, g, eps2, kernel, quad);
+        ax[k - tail] += fx;
+        ay[k - tail] += fy;
+    }
+
+    Point { x: hsum(&ax), y: hsum(&ay) }
+}
+
+pub const GROUP: usize = 8; // particles that share one tree walk
+
+#[allow(clippy::too_many_arguments)]
+pub fn simd_forces(
+    x: &[Real],
+    y: &[Real],
+    a_old: &[Real],
+    id: &[u32],
+    tree: &QuadTree,
+    soft: &[Real],
+    params: &TreeParams,
+    list: &mut InteractionList,
+    out: &mut Vec<(Point, u32)>,
+) {
+    /*
+    Forces on the particles at x / y (a ParticleSoa's arrays), GROUP consecutive entries per
+    tree walk, each particle's interactions evaluated in blocks of LANES. id is the index of
+    every entry in the slice the tree was built from, soft is as for tree_force.
+    Pushes (force, interactions) to out in the order of the entries
+     */
+    for start in (0..x.len()).step_by(GROUP) {
+        let end: usize = (start + GROUP).min(x.len());
+        let (px, py) = (&x[start..end], &y[start..end]);
+        gather(px, py, &a_old[start..end], tree, soft, params, list);
+
+        for k in 0..px.len() {
+            let p: Point = Point { x: px[k], y: py[k] };
+            // a particle inside the tree is always in list once, as a point
+            let own: usize = if tree.area.contains(&p) { 1 } else { 0 };
+            let me: u32 = id[start + k];
+            // the particle's own adaptive softening, if any
+            let mine: TreeParams = match soft.get(me as usize) {
+                Some(&eps2) => TreeParams { eps2, ..*params },
+                None => *params,
+            };
+            out.push((eval_simd(p, me, list, &mine), (list.px.len() + list.nx.len() - own) as u32));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::barnes_hut::{Opening, tree_force};
+    use crate::quadtree::{Rectangle, build_tree};
+    use crate::softening::Adaptive;
+    use crate::vector::Vector;
+
+    fn disk(n: usize) -> Vec<Particle> {
+        (0..n)
+            .map(|i| {
+                let t: Real = i as Real * 2.399;
+                let r: Real = 70.0 * ((i as Real + 0.5) / n as Real).sqrt();
+                Particle::new(i as u64, Point::new(r * t.cos(), r * t.sin()), Vector { x: 0.0, y: 0.0 }, 1.0)
+            })
+            .collect()
+    }
+
+    fn params(order: Multipole) -> TreeParams {
+        TreeParams {
+            theta: 0.7,
+            g: 20.0,
+            coulomb: 0.0,
+            eps2: 1e-3,
+            softening: Softening::Plummer,
+            adaptive: None,
+            order,
+            opening: Opening::Geometric,
+            simd: true,
+        }
+    }
+
+    #[test]
+    fn test_simd_matches_scalar() {
+        let bounds: Rectangle = Rectangle::new(Point::new(-100.0, -75.0), Point::new(100.0, 75.0));
+        let particles: Vec<Particle> = disk(1500);
+        let tree: QuadTree = build_tree(&particles, bounds);
+        let mut soa: ParticleSoa = ParticleSoa::new();
+        soa.load(&particles, &tree);
+        let mut list: InteractionList = InteractionList::new();
+
+        for order in [Multipole::Monopole, Multipole::Quadrupole] {
+            let params: TreeParams = params(order);
+            let exact_params: TreeParams = TreeParams { theta: 0.0, ..params };
+            let mut out: Vec<(Point, u32)> = Vec::new();
+            simd_forces(&soa.x, &soa.y, &soa.a_old, &soa.id, &tree, &[], &params, &mut list, &mut out);
+
+            let mut err_single: Real = 0.0;
+            let mut err_group: Real = 0.0;
+            let (mut walked_single, mut walked_group): (u32, u32) = (0, 0);
+            // out is in the SoA's order, entry k is particle soa.id[k]
+            for (k, &i) in soa.id.iter().enumerate() {
+                let p: Point = particles[i as usize].position;
+                let mut count: u32 = 0;
+                let single: Point = tree_force(p, Some(i), &tree, &[], &params, 0.0, &mut count);
+                // the group walk opens at least the nodes a single walk would
+                assert!(out[k].1 >= count);
+                walked_single += count;
+                walked_group += out[k].1;
+
+                let exact: Point = tree_force(p, Some(i), &tree, &[], &exact_params, 0.0, &mut count);
+                err_single += single.distance(&exact);
+                err_group += out[k].0.distance(&exact);
+
+                // blocked and one-at-a-time evaluation of the same list
+                let start: usize = k - k % GROUP;
+                let end: usize = (start + GROUP).min(particles.len());
+                gather(&soa.x[start..end], &soa.y[start..end], &soa.a_old[start..end], &tree, &[], &params, &mut list);
+                let blocked: Point = eval_simd(p, i, &list, &params);
+                let scalar: Point = eval_scalar(p, i, &list, &params);
+                let scale: Real = (scalar.x * scalar.x + scalar.y * scalar.y).sqrt().max(1e-6);
+                assert!(blocked.distance(&scalar) / scale < 1e-4);
+                assert_eq!((blocked.x, blocked.y), (out[k].0.x, out[k].0.y));
+            }
+            assert!(err_group <= 1.1 * err_single, "{order:?}: group {err_group} single {err_single}");
+            // the disk's particles are in angle order, far apart, but the groups are neighbors in the tree
+            // (about 5 times the single walks' interactions if grouped in that order)
+            assert!(walked_group < 2 * walked_single, "{order:?}: group {walked_group} single {walked_single}");
+        }
+    }
+
+    #[test]
+    fn test_soa_load() {
+        // the entries follow the tree, the particles outside of it come last
+        let bounds: Rectangle = Rectangle::new(Point::new(-50.0, -50.0), Point::new(50.0, 50.0));
+        let particles: Vec<Particle> = disk(37);
+        let tree: QuadTree = build_tree(&particles, bounds);
+        let inside: usize = tree.index.len();
+        assert!(inside < particles.len());
+        let mut soa: ParticleSoa = ParticleSoa::new();
+        soa.load(&particles, &tree);
+        assert_eq!(soa.len(), 37);
+        assert_eq!(&soa.id[..inside], &tree.index[..]);
+        assert!(soa.id[inside..].windows(2).all(|w| w[0] < w[1]));
+        assert!(soa.id[inside..].iter().all(|&i| !bounds.contains(&particles[i as usize].position)));
+        for (k, &i) in soa.id.iter().enumerate() {
+            let p: Point = particles[i as usize].position;
+            assert_eq!((soa.x[k], soa.y[k]), (p.x, p.y));
+        }
+        // a reload replaces the copy
+        soa.load(&particles[..5], &build_tree(&particles[..5], bounds));
+        assert_eq!(soa.len(), 5);
+    }
+
+    #[test]
+    fn test_adaptive_softening_matches_scalar() {
+        let bounds: Rectangle = Rectangle::new(Point::new(-100.0, -75.0), Point::new(100.0, 75.0));
+        // a dense core (the inner half of the disk shrunk 10 times) inside a sparse ring
+        let mut particles: Vec<Particle> = disk(1200);
+        for p in &mut particles[..600] {
+            p.position = p.position.mult_scalar(0.1);
+        }
+        let mut tree: QuadTree = QuadTree::new(bounds);
+        tree.rebuild_morton(&particles, bounds);
+        let adaptive: Adaptive = Adaptive { neighbors: 8, eta: 0.5, min_eps2: 1e-4, max_eps2: 25.0 };
+        let soft: Vec<Real> = adaptive.lengths(&particles, &tree);
+        assert_eq!(soft.len(), particles.len());
+        let core: Real = soft[..600].iter().sum();
+        let ring: Real = soft[600..].iter().sum();
+        assert!(50.0 * core < ring, "core {core} ring {ring}");
+
+        let mut soa: ParticleSoa = ParticleSoa::new();
+        soa.load(&particles, &tree);
+        let mut list: InteractionList = InteractionList::new();
+        for softening in [Softening::Plummer, Softening::Spline, Softening::Polynomial] {
+            let params: TreeParams = TreeParams { softening, theta: 0.0, ..params(Multipole::Monopole) };
+            let mut out: Vec<(Point, u32)> = Vec::new();
+            simd_forces(&soa.x, &soa.y, &soa.a_old, &soa.id, &tree, &soft, &params, &mut list, &mut out);
+            let mut total: Point = Point::zero();
+            let mut size: Real = 0.0;
+            for (k, &i) in soa.id.iter().enumerate() {
+                let mut count: u32 = 0;
+                let single: Point = tree_force(particles[i as usize].position, Some(i), &tree, &soft, &params, 0.0, &mut count);
+                let scale: Real = (single.x * single.x + single.y * single.y).sqrt().max(1e-3);
+                assert!(out[k].0.distance(&single) / scale < 1e-3, "{softening:?} particle {i}");
+                total = total.add(&single);
+                size += scale;
+            }
+            // direct sum with symmetric pair softening: the forces cancel
+            assert!((total.x * total.x + total.y * total.y).sqrt() < 1e-4 * size, "{softening:?} net force {total:?}");
+        }
+    }
+}
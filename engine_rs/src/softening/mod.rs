@@ -0,0 +1,230 @@
+use crate::Real;
+use crate::quadtree::{Point, QuadTree};
+use crate::vector::Particle;
+use rayon::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Softening {
+    /*
+    Gravitational softening kernel. eps2 is always the Plummer-equivalent eps^2: the compact
+    kernels are scaled so their potential at r = 0 is -1 / eps, like Plummer's, and are
+    exactly Newtonian beyond their support radius h
+     */
+    Plummer,    // 1 / (r^2 + eps^2)^(3/2), softened at every distance
+    Spline,     // Monaghan cubic spline as used by Gadget-2, h = 2.8 eps
+    Polynomial, // compact C1 polynomial, a / r = (5/2 - 3/2 u^2) / h^3 for u = r / h < 1, h = 1.875 eps
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Adaptive {
+    /*
+    Per-particle softening from the local density: eps = eta * (distance to the
+    neighbors-th nearest other particle), clamped to [min_eps2, max_eps2] as eps^2.
+    Pairs use the larger of their two values, so the pair force stays symmetric
+     */
+    pub neighbors: usize,
+    pub eta: Real,
+    pub min_eps2: Real,
+    pub max_eps2: Real,
+}
+
+impl Adaptive {
+    pub fn lengths(&self, particles: &[Particle], tree: &QuadTree) -> Vec<Real> {
+        /*
+        Softening eps^2 of every particle by index, from the local density: the distance to the
+        k-th nearest neighbor shrinks where particles crowd together. tree must have been built
+        from particles, the ones outside of it get max_eps2
+         */
+        let eps2_at = |p: Point, out: &mut Vec<u32>| -> Real {
+            out.clear();
+            // the particle itself is its own nearest neighbor
+            tree.nearest(p, self.neighbors + 1, out);
+            let Some(&far) = out.last() else {
+                return self.max_eps2;
+            };
+            let q: Point = particles[far as usize].position;
+            let (dx, dy) = (q.x - p.x, q.y - p.y);
+            (self.eta * self.eta * (dx * dx + dy * dy)).clamp(self.min_eps2, self.max_eps2)
+        };
+        let mut soft: Vec<Real> = vec![self.max_eps2; particles.len()];
+        let pts: Vec<(u32, Point)> = tree.iter().collect();
+        let values: Vec<Real> = if tree.parallel {
+            pts.par_iter().map_init(Vec::new, |out, &(_, p)| eps2_at(p, out)).collect()
+        } else {
+            let mut out: Vec<u32> = Vec::new();
+            pts.iter().map(|&(_, p)| eps2_at(p, &mut out)).collect()
+        };
+        for (&(i, _), v) in pts.iter().zip(values) {
+            soft[i as usize] = v;
+        }
+        soft
+    }
+}
+
+const SPLINE_H: Real = 2.8;
+const POLY_H: Real = 1.875;
+
+impl Softening {
+    fn support2(self, eps2: Real) -> Real {
+        // h^2 of the compact kernels
+        match self {
+            Softening::Plummer => 0.0,
+            Softening::Spline => SPLINE_H * SPLINE_H * eps2,
+            Softening::Polynomial => POLY_H * POLY_H * eps2,
+        }
+    }
+
+    pub fn inv_r3(self, r2: Real, eps2: Real) -> Real {
+        /*
+        Softened 1 / r^3 for an unsoftened squared distance r2: the acceleration toward a
+        mass GM at offset d is GM * d * inv_r3. Zero when both r2 and the softening are zero
+         */
+        if self == Softening::Plummer {
+            let s2: Real = r2 + eps2;
+            return if s2 == 0.0 { 0.0 } else { 1.0 / (s2 * s2.sqrt()) };
+        }
+        let h2: Real = self.support2(eps2);
+        if r2 >= h2 {
+            return if r2 == 0.0 { 0.0 } else { 1.0 / (r2 * r2.sqrt()) };
+        }
+        let h: Real = h2.sqrt();
+        let u: Real = (r2 / h2).sqrt();
+        let inv_h3: Real = 1.0 / (h2 * h);
+        match self {
+            Softening::Spline if u < 0.5 => inv_h3 * (32.0 / 3.0 + u * u * (32.0 * u - 38.4)),
+            Softening::Spline => {
+                inv_h3 * (64.0 / 3.0 - 48.0 * u + 38.4 * u * u - 32.0 / 3.0 * u * u * u - 1.0 / (15.0 * u * u * u))
+            }
+            _ => inv_h3 * (2.5 - 1.5 * u * u),
+        }
+    }
+
+    pub fn inv_r3_block<const N: usize>(self, r2: &[Real; N], eps2: &[Real; N]) -> [Real; N] {
+        /*
+        inv_r3 for a block of N lanes, bit for bit. The kernel is matched once outside the lane
+        loops, and each loop computes every candidate and selects per lane instead of branching,
+        so LLVM can keep it in vector registers (the selects become blends)
+         */
+        let mut out: [Real; N] = [0.0; N];
+        match self {
+            Softening::Plummer => {
+                for l in 0..N {
+                    let s2: Real = r2[l] + eps2[l];
+                    let inv: Real = 1.0 / (s2 * s2.sqrt());
+                    out[l] = if s2 == 0.0 { 0.0 } else { inv };
+                }
+            }
+            Softening::Spline | Softening::Polynomial => {
+                let h2: Real = self.support2(1.0);
+                let newton = |r2: Real| if r2 == 0.0 { 0.0 } else { 1.0 / (r2 * r2.sqrt()) };
+                // far interactions are the common case, a block with no lane inside the support
+                // skips the softened forms (one branch per block, not per lane)
+                let inside: bool = (0..N).fold(false, |any, l| any | (r2[l] < h2 * eps2[l]));
+                if !inside {
+                    for l in 0..N {
+                        out[l] = newton(r2[l]);
+                    }
+                    return out;
+                }
+                let h3 = |h2: Real| 1.0 / (h2 * h2.sqrt());
+                if self == Softening::Spline {
+                    for l in 0..N {
+                        let h2: Real = h2 * eps2[l];
+                        let (inv_h3, u): (Real, Real) = (h3(h2), (r2[l] / h2).sqrt());
+                        let inner: Real = inv_h3 * (32.0 / 3.0 + u * u * (32.0 * u - 38.4));
+                        let outer: Real =
+                            inv_h3 * (64.0 / 3.0 - 48.0 * u + 38.4 * u * u - 32.0 / 3.0 * u * u * u - 1.0 / (15.0 * u * u * u));
+                        let soft: Real = if u < 0.5 { inner } else { outer };
+                        out[l] = if r2[l] >= h2 { newton(r2[l]) } else { soft };
+                    }
+                } else {
+                    for l in 0..N {
+                        let h2: Real = h2 * eps2[l];
+                        let (inv_h3, u): (Real, Real) = (h3(h2), (r2[l] / h2).sqrt());
+                        let soft: Real = inv_h3 * (2.5 - 1.5 * u * u);
+                        out[l] = if r2[l] >= h2 { newton(r2[l]) } else { soft };
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    pub fn potential(self, r2: Real, eps2: Real) -> Real {
+        /*
+        Softened -1 / r (potential of a unit GM), for energy diagnostics
+         */
+        if self == Softening::Plummer {
+            return -1.0 / (r2 + eps2).sqrt();
+        }
+        let h2: Real = self.support2(eps2);
+        if r2 >= h2 {
+            return -1.0 / r2.sqrt();
+        }
+        let h: Real = h2.sqrt();
+        let u: Real = (r2 / h2).sqrt();
+        let w: Real = match self {
+            Softening::Spline if u < 0.5 => -2.8 + u * u * (16.0 / 3.0 + u * u * (6.4 * u - 9.6)),
+            Softening::Spline => -3.2 + 1.0 / (15.0 * u) + u * u * (32.0 / 3.0 + u * (-16.0 + u * (9.6 - 32.0 / 15.0 * u))),
+            _ => -1.875 + u * u * (1.25 - 0.375 * u * u),
+        };
+        w / h
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KERNELS: [Softening; 3] = [Softening::Plummer, Softening::Spline, Softening::Polynomial];
+
+    #[test]
+    fn test_force_is_minus_gradient() {
+        // a = -dphi/dr, so inv_r3 * r must match the slope of the potential
+        let eps2: Real = 4.0;
+        for kernel in KERNELS {
+            for k in 1..60 {
+                let r: f64 = k as f64 * 0.25;
+                let dr: f64 = 1e-3;
+                let phi = |r: f64| kernel.potential((r * r) as Real, eps2) as f64;
+                let slope: f64 = (phi(r + dr) - phi(r - dr)) / (2.0 * dr);
+                let a: f64 = kernel.inv_r3((r * r) as Real, eps2) as f64 * r;
+                assert!((a - slope).abs() < 2e-3 * (1.0 + a), "{kernel:?} at r = {r}: {a} vs {slope}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_kernels_match_at_center_and_far_away() {
+        let eps2: Real = 4.0;
+        for kernel in KERNELS {
+            // same depth as Plummer at r = 0
+            assert!((kernel.potential(0.0, eps2) + 0.5).abs() < 1e-4, "{kernel:?}");
+            assert!(kernel.inv_r3(0.0, eps2).is_finite());
+        }
+        // the compact kernels are Newtonian beyond h
+        let r2: Real = 36.0;
+        for kernel in [Softening::Spline, Softening::Polynomial] {
+            assert_eq!(kernel.inv_r3(r2, eps2), 1.0 / 216.0);
+            assert_eq!(kernel.potential(r2, eps2), -1.0 / 6.0);
+        }
+        // and unsoftened without eps
+        for kernel in KERNELS {
+            assert_eq!(kernel.inv_r3(4.0, 0.0), 0.125);
+            assert_eq!(kernel.inv_r3(0.0, 0.0), 0.0);
+        }
+    }
+
+    #[test]
+    fn test_block_matches_lane_by_lane() {
+        // inside, outside and at the support radius, at the center, and without softening
+        let r2: [Real; 8] = [0.0, 0.5, 3.0, 7.84, 20.0, 36.0, 0.0, 9.0];
+        let eps2: [Real; 8] = [1.0, 1.0, 1.0, 1.0, 4.0, 4.0, 0.0, 0.0];
+        for kernel in KERNELS {
+            let block: [Real; 8] = kernel.inv_r3_block(&r2, &eps2);
+            for l in 0..8 {
+                assert_eq!(block[l].to_bits(), kernel.inv_r3(r2[l], eps2[l]).to_bits(), "{kernel:?} lane {l}");
+            }
+        }
+    }
+}
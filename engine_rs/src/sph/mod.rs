@@ -0,0 +1,260 @@
+use crate::Real;
+use crate::quadtree::{Point, QuadTree};
+use crate::vector::{Kind, Particle, Vector};
+use rayon::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Eos {
+    /*
+    Equation of state giving the gas pressure
+     */
+    Isothermal { cs: Real },   // P = cs^2 rho, the sound speed never changes
+    Adiabatic { gamma: Real }, // P = (gamma - 1) rho u, u evolves with compression and viscous heating
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sph {
+    /*
+    Smoothed particle hydrodynamics for the Kind::Gas particles: densities are kernel sums
+    over the gas neighbors within 2h, found with the tree, and the pressure and Monaghan
+    artificial viscosity forces are applied in pairs, so momentum is conserved
+     */
+    pub eos: Eos,
+    pub h: Real,     // smoothing length, the kernel reaches 2h
+    pub alpha: Real, // linear viscosity, ~1
+    pub beta: Real,  // quadratic viscosity against particle interpenetration in shocks, ~2
+}
+
+pub fn kernel(r2: Real, h: Real) -> Real {
+    /*
+    2D cubic spline W(r, h) (Monaghan & Lattanzio), integrates to 1
+     */
+    let sigma: Real = 10.0 / (7.0 * std::f64::consts::PI as Real * h * h);
+    let q: Real = r2.sqrt() / h;
+    if q < 1.0 {
+        sigma * (1.0 - 1.5 * q * q + 0.75 * q * q * q)
+    } else if q < 2.0 {
+        sigma * 0.25 * (2.0 - q).powi(3)
+    } else {
+        0.0
+    }
+}
+
+pub fn kernel_grad(r2: Real, h: Real) -> Real {
+    /*
+    dW/dr / r, so the gradient of W(|x_i - x_j|) with respect to x_i is (x_i - x_j) * kernel_grad
+     */
+    if r2 == 0.0 {
+        return 0.0;
+    }
+    let sigma: Real = 10.0 / (7.0 * std::f64::consts::PI as Real * h * h);
+    let r: Real = r2.sqrt();
+    let q: Real = r / h;
+    let dw: Real = if q < 1.0 {
+        sigma / h * (-3.0 * q + 2.25 * q * q)
+    } else if q < 2.0 {
+        -sigma / h * 0.75 * (2.0 - q) * (2.0 - q)
+    } else {
+        0.0
+    };
+    dw / r
+}
+
+fn energy_of(p: &Particle) -> Option<Real> {
+    match p.kind {
+        Kind::Gas { energy } => Some(energy),
+        Kind::Collisionless => None,
+    }
+}
+
+impl Sph {
+    pub fn pressure(&self, rho: Real, u: Real) -> Real {
+        match self.eos {
+            Eos::Isothermal { cs } => cs * cs * rho,
+            Eos::Adiabatic { gamma } => (gamma - 1.0) * rho * u,
+        }
+    }
+
+    pub fn sound_speed(&self, u: Real) -> Real {
+        match self.eos {
+            Eos::Isothermal { cs } => cs,
+            Eos::Adiabatic { gamma } => (gamma * (gamma - 1.0) * u.max(0.0)).sqrt(),
+        }
+    }
+
+    fn gas_neighbors(&self, i: usize, particles: &[Particle], tree: &QuadTree, out: &mut Vec<u32>) {
+        // the other gas particles within the kernel's reach of particle i
+        out.clear();
+        let p: Point = particles[i].position;
+        if tree.area.contains(&p) {
+            tree.query_radius(p, 2.0 * self.h, out);
+        }
+        out.retain(|&j| j as usize != i && energy_of(&particles[j as usize]).is_some());
+    }
+
+    pub fn densities(&self, particles: &[Particle], tree: &QuadTree, parallel: bool) -> Vec<Real> {
+        /*
+        Kernel density of every gas particle, its own mass included (0 for collisionless
+        particles). tree must have been built from particles
+         */
+        let density_of = |i: usize, out: &mut Vec<u32>| -> Real {
+            let p: &Particle = &particles[i];
+            if energy_of(p).is_none() {
+                return 0.0;
+            }
+            self.gas_neighbors(i, particles, tree, out);
+            out.iter().fold(p.mass * kernel(0.0, self.h), |rho, &j| {
+                let q: &Particle = &particles[j as usize];
+                let (dx, dy) = (p.position.x - q.position.x, p.position.y - q.position.y);
+                rho + q.mass * kernel(dx * dx + dy * dy, self.h)
+            })
+        };
+        if parallel {
+            (0..particles.len()).into_par_iter().map_init(Vec::new, |out, i| density_of(i, out)).collect()
+        } else {
+            let mut out: Vec<u32> = Vec::new();
+            (0..particles.len()).map(|i| density_of(i, &mut out)).collect()
+        }
+    }
+
+    pub fn accels(&self, particles: &[Particle], tree: &QuadTree, density: &[Real], parallel: bool) -> Vec<(Vector, Real)> {
+        /*
+        Pressure plus viscosity acceleration of every gas particle and the rate of change of
+        its internal energy (0 for the isothermal equation of state and collisionless particles)
+         */
+        let h2: Real = self.h * self.h;
+        let adiabatic: bool = matches!(self.eos, Eos::Adiabatic { .. });
+        let accel_of = |i: usize, out: &mut Vec<u32>| -> (Vector, Real) {
+            let p: &Particle = &particles[i];
+            let Some(u_i) = energy_of(p) else {
+                return (Vector { x: 0.0, y: 0.0 }, 0.0);
+            };
+            let rho_i: Real = density[i];
+            let over_i: Real = self.pressure(rho_i, u_i) / (rho_i * rho_i); // P / rho^2
+            let c_i: Real = self.sound_speed(u_i);
+            self.gas_neighbors(i, particles, tree, out);
+
+            let mut a: Vector = Vector { x: 0.0, y: 0.0 };
+            let mut du: Real = 0.0;
+            for &j in out.iter() {
+                let q: &Particle = &particles[j as usize];
+                let u_j: Real = energy_of(q).unwrap_or(0.0);
+                let rho_j: Real = density[j as usize];
+                let (dx, dy) = (p.position.x - q.position.x, p.position.y - q.position.y);
+                let r2: Real = dx * dx + dy * dy;
+                let gw: Real = kernel_grad(r2, self.h);
+                if gw == 0.0 {
+                    continue;
+                }
+                // approaching pairs get the artificial viscosity
+                let vdotr: Real = (p.velocity.x - q.velocity.x) * dx + (p.velocity.y - q.velocity.y) * dy;
+                let visc: Real = if vdotr < 0.0 {
+                    let mu: Real = self.h * vdotr / (r2 + 0.01 * h2);
+                    let c: Real = 0.5 * (c_i + self.sound_speed(u_j));
+                    (-self.alpha * c * mu + self.beta * mu * mu) / (0.5 * (rho_i + rho_j))
+                } else {
+                    0.0
+                };
+                let over_j: Real = self.pressure(rho_j, u_j) / (rho_j * rho_j);
+                let f: Real = q.mass * (over_i + over_j + visc) * gw;
+                a.x -= f * dx;
+                a.y -= f * dy;
+                du += q.mass * (over_i + 0.5 * visc) * vdotr * gw;
+            }
+            (a, if adiabatic { du } else { 0.0 })
+        };
+        if parallel {
+            (0..particles.len()).into_par_iter().map_init(Vec::new, |out, i| accel_of(i, out)).collect()
+        } else {
+            let mut out: Vec<u32> = Vec::new();
+            (0..particles.len()).map(|i| accel_of(i, &mut out)).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quadtree::{Rectangle, build_tree};
+
+    fn lattice(n: usize, spacing: Real, kind: Kind) -> Vec<Particle> {
+        (0..n * n)
+            .map(|k| {
+                let position: Point = Point::new((k % n) as Real * spacing, (k / n) as Real * spacing);
+                Particle { kind, ..Particle::new(k as u64, position, Vector { x: 0.0, y: 0.0 }, 1.0) }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_kernel() {
+        // integrates to 1 over the plane
+        let h: Real = 1.3;
+        let cell: f64 = 0.02;
+        let mut total: f64 = 0.0;
+        for i in -150..150 {
+            for j in -150..150 {
+                let (x, y) = ((i as f64 + 0.5) * cell, (j as f64 + 0.5) * cell);
+                total += kernel((x * x + y * y) as Real, h) as f64 * cell * cell;
+            }
+        }
+        assert!((total - 1.0).abs() < 1e-3, "integral {total}");
+        // kernel_grad * r is the slope of the kernel
+        for k in 1..60 {
+            let r: f64 = k as f64 * 0.05;
+            let dr: f64 = 1e-3;
+            let w = |r: f64| kernel((r * r) as Real, h) as f64;
+            let slope: f64 = (w(r + dr) - w(r - dr)) / (2.0 * dr);
+            let g: f64 = kernel_grad((r * r) as Real, h) as f64 * r;
+            assert!((g - slope).abs() < 1e-3, "r = {r}: {g} vs {slope}");
+        }
+    }
+
+    #[test]
+    fn test_uniform_lattice() {
+        // the density inside a lattice is mass / cell area and the pressure forces cancel there,
+        // while the edge is pushed out. Overall they cancel pairwise
+        let spacing: Real = 0.5;
+        let mut particles: Vec<Particle> = lattice(30, spacing, Kind::Gas { energy: 1.0 });
+        particles[0].velocity = Vector { x: 3.0, y: 1.0 }; // a corner with some viscosity
+        let bounds: Rectangle = Rectangle::new(Point::new(-1.0, -1.0), Point::new(16.0, 16.0));
+        let tree: QuadTree = build_tree(&particles, bounds);
+        let sph: Sph = Sph { eos: Eos::Adiabatic { gamma: 5.0 / 3.0 }, h: 0.6, alpha: 1.0, beta: 2.0 };
+        let rho: Vec<Real> = sph.densities(&particles, &tree, false);
+        let center: usize = 15 * 30 + 15;
+        assert!((rho[center] - 4.0).abs() < 0.05 * 4.0, "density {}", rho[center]);
+
+        let out: Vec<(Vector, Real)> = sph.accels(&particles, &tree, &rho, true);
+        let edge: Vector = out[15 * 30].0; // middle of the left edge
+        assert!(out[center].0.x.abs() < 1e-2 * edge.x.abs() && out[center].0.y.abs() < 1e-2 * edge.x.abs());
+        assert!(edge.x < 0.0);
+        let net: Vector = out.iter().fold(Vector { x: 0.0, y: 0.0 }, |n, o| n + o.0);
+        let scale: Real = out.iter().map(|o| o.0.x.abs() + o.0.y.abs()).sum();
+        assert!(net.x.abs() < 1e-4 * scale && net.y.abs() < 1e-4 * scale);
+        // viscous heating where the corner moves, nothing where the gas is at rest
+        assert!(out[0].1 != 0.0 && out[center].1 == 0.0);
+    }
+
+    #[test]
+    fn test_collisionless_particles_are_ignored() {
+        let mut particles: Vec<Particle> = lattice(10, 0.5, Kind::Gas { energy: 1.0 });
+        let stars: Vec<Particle> = lattice(10, 0.5, Kind::Collisionless);
+        let n: usize = particles.len();
+        particles.extend(stars.iter().map(|s| Particle { id: s.id + 1000, ..*s }));
+        let bounds: Rectangle = Rectangle::new(Point::new(-1.0, -1.0), Point::new(6.0, 6.0));
+        let tree: QuadTree = build_tree(&particles, bounds);
+        let sph: Sph = Sph { eos: Eos::Isothermal { cs: 1.0 }, h: 0.6, alpha: 1.0, beta: 2.0 };
+        let rho: Vec<Real> = sph.densities(&particles, &tree, false);
+        let out: Vec<(Vector, Real)> = sph.accels(&particles, &tree, &rho, false);
+
+        let gas_only: Vec<Particle> = particles[..n].to_vec();
+        let gas_tree: QuadTree = build_tree(&gas_only, bounds);
+        let alone: Vec<Real> = sph.densities(&gas_only, &gas_tree, false);
+        for (a, b) in rho[..n].iter().zip(&alone) {
+            assert!((a - b).abs() < 1e-5 * b);
+        }
+        for (r, o) in rho[n..].iter().zip(&out[n..]) {
+            assert_eq!((*r, o.0.x, o.0.y, o.1), (0.0, 0.0, 0.0, 0.0));
+        }
+    }
+}
@@ -1,29 +1,31 @@
 use std::ops::{Add, Mul, Sub};
 
+use crate::Real;
 use crate::quadtree::Point;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Vector {
-    pub x: f32,
-    pub y: f32,
+    pub x: Real,
+    pub y: Real,
 }
 
+#[allow(dead_code)] // the helpers only the tests use so far
 impl Vector {
 
-    fn new(x: f32, y: f32) -> Vector {
-        Vector { x: x, y: y }
+    fn new(x: Real, y: Real) -> Vector {
+        Vector { x, y }
     }
 
     fn equal_vectors(a: &Vector, b: &Vector) -> bool {
-        a.x == b.x && a.y == b.y 
+        a.x == b.x && a.y == b.y
     }
 
-    fn length(a: &Vector) -> f32 {
+    fn length(a: &Vector) -> Real {
         ((a.x).powi(2) + (a.y).powi(2)).sqrt()
     }
 
     fn unit(a: &Vector) -> Vector {
-        let magnitude: f32 = Self::length(a);
+        let magnitude: Real = Self::length(a);
 
         Vector {
             x: a.x / magnitude,
@@ -31,18 +33,18 @@ impl Vector {
         }
     }
 
-    pub fn mult_scalar(&self, scalar: f32) -> Vector {
+    pub fn mult_scalar(&self, scalar: Real) -> Vector {
         Vector {
             x: self.x * scalar,
             y: self.y * scalar,
         }
     }
 
-    fn dot(a: &Vector, b: &Vector) -> f32 {
+    fn dot(a: &Vector, b: &Vector) -> Real {
         (a.x * b.x) + (a.y * b.y)
     }
 
-    fn cross(a: &Vector, b: &Vector) -> f32 {
+    fn cross(a: &Vector, b: &Vector) -> Real {
         a.x * b.y - a.y * b.x
     }
     
@@ -84,11 +86,64 @@ impl Mul for Vector {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    /*
+    Whether a particle is collisionless (stars, dark matter: gravity only) or a parcel of gas
+    that also feels pressure when SPH is on
+     */
+    Collisionless,
+    Gas { energy: Real }, // specific internal energy u, only evolved by the adiabatic equation of state
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Spin {
+    /*
+    Orientation and rotation of a particle treated as a rigid disk
+     */
+    pub inertia: Real, // moment of inertia about the center
+    pub angle: Real,   // radians, counter-clockwise
+    pub omega: Real,   // angular velocity, counter-clockwise for omega > 0
+}
+
+impl Spin {
+    pub fn disk(mass: Real, radius: Real) -> Self {
+        // a uniform disk at rest, I = m r^2 / 2
+        Spin { inertia: 0.5 * mass * radius * radius, angle: 0.0, omega: 0.0 }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Particle {
+    pub id: u64, // stable identity, stays with the particle when the array is reordered
     pub position: Point,
     pub velocity: Vector,
-    pub mass: f32,
+    pub accel: Vector, // acceleration from the last step
+    pub mass: Real,
+    pub charge: Real, // electric charge, 0 for neutral
+    pub kind: Kind,
+    pub spin: Option<Spin>, // rotation as a rigid disk, None for a point that never turns
+    pub tag: Option<u32>, // optional user group, e.g. which cluster a particle started in
+}
+
+impl Particle {
+    pub fn new(id: u64, position: Point, velocity: Vector, mass: Real) -> Self {
+        /*
+        A neutral, collisionless particle at rest in rotation and without a tag. Set the other
+        fields with struct update syntax: Particle { charge, ..Particle::new(..) }
+         */
+        Particle {
+            id,
+            position,
+            velocity,
+            accel: Vector { x: 0.0, y: 0.0 },
+            mass,
+            charge: 0.0,
+            kind: Kind::Collisionless,
+            spin: None,
+            tag: None,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -100,32 +155,29 @@ mod tests {
         let a: Vector = Vector::new(1.0, 2.0);
         let b: Vector = Vector::new(1.0, 2.0);
 
-        assert_eq!(Vector::equal_vectors(&a, &b), true)
+        assert!(Vector::equal_vectors(&a, &b))
     }
 
     #[test]
     fn test_length() {
         let sample: Vector = Vector::new(1.0, 2.0);
-        assert_eq!(Vector::length(&sample), 3.74165738677)
+        assert_eq!(Vector::length(&sample), (5.0 as Real).sqrt())
     }
 
     #[test]
     fn test_unit_vector() {
         let sample: Vector = Vector::new(1.0, 2.0);
-        assert_eq!(
-            Vector::unit(&sample),
-            Vector {
-                x: (1.0 / 3.74165738677),
-                y: (2.0 / 3.74165738677),
-            }
-        )
+        let unit: Vector = Vector::unit(&sample);
+        let len: Real = (5.0 as Real).sqrt();
+        assert!((unit.x - 1.0 / len).abs() < 1e-6 && (unit.y - 2.0 / len).abs() < 1e-6);
+        assert!((Vector::length(&unit) - 1.0).abs() < 1e-6)
     }
 
     #[test]
     fn test_dot() {
         let a: Vector = Vector::new(1.0, 2.0);
         let b: Vector = Vector::new(3.0, 4.0);
-        assert_eq!(Vector::dot(&a, &b), 26.0)
+        assert_eq!(Vector::dot(&a, &b), 11.0)
     }
 
     #[test]
@@ -0,0 +1,53 @@
+#![allow(dead_code)] // each test file uses a different subset
+
+use engine_rs::Real;
+use engine_rs::barnes_hut::{Execution, Multipole, Opening, SimParams, TreeParams, step_barnes_hut};
+use engine_rs::quadtree::{Point, QuadTree, Rectangle};
+use engine_rs::softening::Softening;
+use engine_rs::vector::{Particle, Vector};
+
+/*
+Fixtures shared by the integration tests
+ */
+
+pub fn particle(id: u64, x: Real, y: Real, vx: Real, vy: Real) -> Particle {
+    Particle::new(id, Point { x, y }, Vector { x: vx, y: vy }, 1.0)
+}
+
+pub fn gravity(g: Real) -> TreeParams {
+    TreeParams {
+        theta: 0.7,
+        g,
+        coulomb: 0.0,
+        eps2: 0.0,
+        softening: Softening::Plummer,
+        adaptive: None,
+        order: Multipole::Monopole,
+        opening: Opening::Geometric,
+        simd: false,
+    }
+}
+
+pub fn isolated() -> SimParams {
+    // nothing but the forces under test: no central mass, speed limit or damping
+    SimParams {
+        attractors: Vec::new(),
+        collisions: false,
+        vmax: Real::INFINITY,
+        damping: None,
+        ..SimParams::default()
+    }
+}
+
+pub fn coast(sim: &SimParams, start: Vec<Particle>, dt: Real, steps: usize) -> Vec<Particle> {
+    // steps particles that only feel sim (no tree gravity, no collisions)
+    let bounds: Rectangle = Rectangle::new(Point::new(-1e4, -1e4), Point::new(1e4, 1e4));
+    let mut particles: Vec<Particle> = start;
+    let mut sim: SimParams = sim.clone();
+    let mut tree: QuadTree = QuadTree::new(bounds);
+    for k in 0..steps {
+        tree.rebuild(&particles, bounds);
+        step_barnes_hut(&mut particles, &tree, k as Real * dt, dt, &gravity(0.0), &mut sim, Execution::Serial);
+    }
+    particles
+}
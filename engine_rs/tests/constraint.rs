@@ -0,0 +1,85 @@
+use engine_rs::Real;
+use engine_rs::barnes_hut::SimParams;
+use engine_rs::constraint::{Constraints, Pin, Rod, Spring};
+use engine_rs::field::Uniform;
+use engine_rs::quadtree::Point;
+use engine_rs::scenario::cloth;
+use engine_rs::vector::{Particle, Vector};
+use std::sync::Arc;
+
+mod common;
+use common::{coast, isolated, particle};
+
+#[test]
+fn test_spring_follows_damped_oscillator() {
+    // two unit masses released from rest with the spring stretched by x0: the stretch follows
+    // the damped oscillator of the reduced mass, x0 e^(-gamma t) (cos wt + gamma / w sin wt),
+    // and the spring never moves their center of mass
+    let (rest, stiffness, x0): (Real, Real, Real) = (2.0, 5.0, 1.0);
+    let start: Vec<Particle> = vec![particle(0, 0.0, 0.0, 0.0, 0.0), particle(1, rest + x0, 0.0, 0.0, 0.0)];
+    let reduced: Real = 0.5;
+    for damping in [0.0, 0.5] {
+        let mut constraints: Constraints = Constraints::default();
+        constraints.springs.push(Spring { a: 0, b: 1, rest, stiffness, damping });
+        let sim: SimParams = SimParams { constraints, ..isolated() };
+        let gamma: Real = damping / (2.0 * reduced);
+        let w: Real = (stiffness / reduced - gamma * gamma).sqrt();
+        let mut particles: Vec<Particle> = start.clone();
+        for k in 1..=10 {
+            particles = coast(&sim, particles, 0.002, 100);
+            let t: Real = k as Real * 0.2;
+            let expected: Real = x0 * (-gamma * t).exp() * ((w * t).cos() + gamma / w * (w * t).sin());
+            let stretch: Real = particles[0].position.distance(&particles[1].position) - rest;
+            assert!((stretch - expected).abs() < 1e-2 * x0, "damping {damping} at t = {t}: {stretch} vs {expected}");
+            let center: Real = 0.5 * (particles[0].position.x + particles[1].position.x);
+            assert!((center - 0.5 * (rest + x0)).abs() < 1e-5, "center of mass moved to {center}");
+        }
+    }
+}
+
+#[test]
+fn test_chain_hangs_from_its_pin() {
+    // a horizontal chain of rods pinned at one end swings down under gravity
+    let start: Vec<Particle> = (0..10).map(|i| particle(i, i as Real, 0.0, 0.0, 0.0)).collect();
+    let mut constraints: Constraints = Constraints { iterations: 20, ..Constraints::default() };
+    for i in 0..9 {
+        constraints.rods.push(Rod { a: i, b: i + 1, length: 1.0 });
+    }
+    constraints.pins.push(Pin { particle: 0, at: Point::zero() });
+    let sim: SimParams = SimParams {
+        fields: vec![Arc::new(Uniform { g: Vector { x: 0.0, y: -9.81 } })],
+        constraints,
+        damping: Some(1.0),
+        ..isolated()
+    };
+    let particles: Vec<Particle> = coast(&sim, start, 1.0 / 60.0, 1200);
+    assert_eq!((particles[0].position.x, particles[0].position.y), (0.0, 0.0));
+    for pair in particles.windows(2) {
+        let len: Real = pair[0].position.distance(&pair[1].position);
+        assert!((len - 1.0).abs() < 1e-2, "rod {} stretched to {len}", pair[0].id);
+    }
+    // settled straight down
+    let tip: Point = particles[9].position;
+    assert!(tip.x.abs() < 0.1 && (tip.y + 9.0).abs() < 0.1, "tip at ({}, {})", tip.x, tip.y);
+}
+
+#[test]
+fn test_cloth_stays_in_one_piece() {
+    let (start, constraints) = cloth(Point::new(-45.0, 65.0), 31, 21, 3.0);
+    let pins: Vec<Pin> = constraints.pins.clone();
+    let sim: SimParams = SimParams {
+        fields: vec![Arc::new(Uniform { g: Vector { x: 0.0, y: -9.81 } })],
+        constraints,
+        ..isolated()
+    };
+    let particles: Vec<Particle> = coast(&sim, start, 1.0 / 15.0, 300);
+    for pin in &pins {
+        let p: Point = particles[pin.particle as usize].position;
+        assert_eq!((p.x, p.y), (pin.at.x, pin.at.y));
+    }
+    let worst: Real = sim.constraints.rods.iter().fold(0.0, |worst, rod| {
+        let len: Real = particles[rod.a as usize].position.distance(&particles[rod.b as usize].position);
+        worst.max((len - rod.length).abs() / rod.length)
+    });
+    assert!(worst < 0.15, "a rod stretched by {worst}");
+}
@@ -0,0 +1,38 @@
+use engine_rs::Real;
+use engine_rs::barnes_hut::SimParams;
+use engine_rs::drag::{Drag, Wind};
+use engine_rs::field::Uniform;
+use engine_rs::vector::{Particle, Vector};
+use std::sync::Arc;
+
+mod common;
+use common::{coast, isolated, particle};
+
+#[test]
+fn test_damping_is_dt_independent() {
+    let sim: SimParams = SimParams { damping: Some(0.5), ..isolated() };
+    let start: Vec<Particle> = vec![particle(0, 0.0, 0.0, 8.0, -6.0)];
+    let coarse: Vec<Particle> = coast(&sim, start.clone(), 0.1, 20);
+    let fine: Vec<Particle> = coast(&sim, start, 0.025, 80);
+    // 2 time units at rate 0.5: the speed of 10 decays to 10 / e
+    let speed: Real = (coarse[0].velocity.x.powi(2) + coarse[0].velocity.y.powi(2)).sqrt();
+    assert!((speed - 10.0 * Real::exp(-1.0)).abs() < 1e-4, "speed {speed}");
+    assert!((coarse[0].velocity.x - fine[0].velocity.x).abs() < 1e-4);
+}
+
+#[test]
+fn test_drag_reaches_terminal_velocity() {
+    // falling under uniform gravity with quadratic drag in a sideways wind:
+    // the relative velocity settles where k |u| u = g
+    let (g, k): (Real, Real) = (9.81, 0.04);
+    let wind: Vector = Vector { x: 4.0, y: 0.0 };
+    let sim: SimParams = SimParams {
+        fields: vec![Arc::new(Uniform { g: Vector { x: 0.0, y: -g } })],
+        drag: Some(Drag { linear: 0.0, quadratic: k, wind: Wind::Uniform(wind) }),
+        ..isolated()
+    };
+    let particles: Vec<Particle> = coast(&sim, vec![particle(0, 0.0, 0.0, 0.0, 0.0)], 0.05, 600);
+    let v: Vector = particles[0].velocity;
+    assert!((v.x - wind.x).abs() < 1e-2, "vx {}", v.x);
+    assert!((v.y + (g / k).sqrt()).abs() < 0.05 * (g / k).sqrt(), "vy {} terminal {}", v.y, -(g / k).sqrt());
+}
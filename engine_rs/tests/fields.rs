@@ -0,0 +1,51 @@
+use engine_rs::Real;
+use engine_rs::barnes_hut::SimParams;
+use engine_rs::field::{Harmonic, LogHalo, Nfw};
+use engine_rs::quadtree::Point;
+use engine_rs::vector::Particle;
+use std::sync::Arc;
+
+mod common;
+use common::{coast, isolated, particle};
+
+#[test]
+fn test_central_fields_keep_angular_momentum() {
+    // test particles in a halo, a cusp and a trap around the same center: every pull points at
+    // the center, so each particle's angular momentum about it only changes by rounding
+    let start: Vec<Particle> = (0..8)
+        .map(|i| {
+            let r: Real = 5.0 + 4.0 * i as Real;
+            particle(i, r, 0.0, 0.5, 2.0 + 0.3 * i as Real)
+        })
+        .collect();
+    let sim: SimParams = SimParams {
+        fields: vec![
+            Arc::new(LogHalo { center: Point::zero(), v0: 3.0, rc: 2.0 }),
+            Arc::new(Nfw { center: Point::zero(), gm: 50.0, rs: 10.0 }),
+            Arc::new(Harmonic { center: Point::zero(), omega: 0.05 }),
+        ],
+        ..isolated()
+    };
+    let spin = |p: &Particle| -> Real { p.mass * (p.position.x * p.velocity.y - p.position.y * p.velocity.x) };
+    let particles: Vec<Particle> = coast(&sim, start.clone(), 0.01, 2000);
+    assert!(particles.iter().any(|p| p.position.y < 0.0), "orbits should have turned");
+    for (before, after) in start.iter().zip(&particles) {
+        let (l0, l1) = (spin(before), spin(after));
+        assert!((l1 - l0).abs() < 1e-4 * l0.abs(), "particle {}: {l0} -> {l1}", before.id);
+    }
+}
+
+#[test]
+fn test_harmonic_trap_period() {
+    // every orbit in a harmonic trap closes after 2 pi / omega, whatever its shape
+    let omega: Real = 0.5;
+    let sim: SimParams = SimParams { fields: vec![Arc::new(Harmonic { center: Point::new(1.0, -2.0), omega })], ..isolated() };
+    let start: Vec<Particle> = vec![particle(0, 4.0, -2.0, 0.0, 0.7), particle(1, 1.0, 3.0, -1.5, 0.2)];
+    let dt: Real = 0.001;
+    let period: Real = 2.0 * std::f32::consts::PI as Real / omega;
+    let particles: Vec<Particle> = coast(&sim, start.clone(), dt, (period / dt).round() as usize);
+    for (before, after) in start.iter().zip(&particles) {
+        let d: Real = after.position.distance(&before.position);
+        assert!(d < 1e-2, "particle {} ended {d} from its start", before.id);
+    }
+}
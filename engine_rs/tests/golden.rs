@@ -0,0 +1,248 @@
+#![allow(clippy::unnecessary_cast)] // Real is already f64 with the f64 feature
+
+use engine_rs::Real;
+use engine_rs::barnes_hut::{Execution, SimParams, TreeParams, step_barnes_hut};
+use engine_rs::quadtree::{Point, QuadTree, Rectangle};
+use engine_rs::rng::DEFAULT_SEED;
+use engine_rs::scenario::{Distribution, generate};
+use engine_rs::vector::Particle;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+mod common;
+use common::{gravity, isolated, particle};
+
+/*
+Golden trajectory tests: small deterministic scenarios are stepped a fixed number of times and
+the sampled states are compared against tests/golden/<name>.csv within a tolerance.
+After a deliberate change to the physics, regenerate the files and review the diff:
+    UPDATE_GOLDEN=1 cargo test --test golden
+    UPDATE_GOLDEN=1 cargo test --test golden --features f64
+ */
+
+struct Scenario {
+    name: &'static str,
+    seed: Option<u64>, // only for scenarios with random starting positions
+    particles: Vec<Particle>,
+    bounds: Rectangle,
+    params: TreeParams,
+    sim: SimParams,
+    dt: Real,
+    steps: usize,
+    every: usize, // steps between recorded samples
+    tol: f64,     // largest allowed absolute difference in any position or velocity
+    chaotic: bool, // f32 and f64 runs diverge, so keep one golden per float type
+}
+
+fn two_body() -> Scenario {
+    // equal masses on a circular orbit of separation 1 (G = 1, period ~4.44)
+    let half: Real = 0.5;
+    let v: Real = half.sqrt();
+    Scenario {
+        name: "two_body",
+        seed: None,
+        particles: vec![particle(0, 0.5, 0.0, 0.0, v), particle(1, -0.5, 0.0, 0.0, -v)],
+        bounds: Rectangle::new(Point::new(-1.0, -1.0), Point::new(1.0, 1.0)),
+        params: gravity(1.0),
+        sim: isolated(),
+        dt: 0.005,
+        steps: 1000,
+        every: 50,
+        tol: 1e-3,
+        chaotic: false,
+    }
+}
+
+#[allow(clippy::excessive_precision)] // the published initial conditions, rounded to f32 by default
+fn figure_eight() -> Scenario {
+    // Chenciner-Montgomery periodic three body orbit (G = m = 1, period ~6.3259)
+    let (x, y): (Real, Real) = (0.97000436, -0.24308753);
+    let (vx, vy): (Real, Real) = (-0.93240737, -0.86473146);
+    Scenario {
+        name: "figure_eight",
+        seed: None,
+        particles: vec![
+            particle(0, x, y, -vx / 2.0, -vy / 2.0),
+            particle(1, 0.0, 0.0, vx, vy),
+            particle(2, -x, -y, -vx / 2.0, -vy / 2.0),
+        ],
+        bounds: Rectangle::new(Point::new(-2.0, -2.0), Point::new(2.0, 2.0)),
+        params: gravity(1.0),
+        sim: isolated(),
+        dt: 6.3259140 / 6000.0, // one period in 6000 steps
+        steps: 6000,
+        every: 500,
+        tol: 1e-3,
+        chaotic: false,
+    }
+}
+
+fn head_on() -> Scenario {
+    // two particles meeting head on with a perfectly elastic collision and no gravity
+    Scenario {
+        name: "head_on",
+        seed: None,
+        particles: vec![particle(0, -5.0, 0.0, 2.0, 0.0), particle(1, 5.0, 0.0, -2.0, 0.0)],
+        bounds: Rectangle::new(Point::new(-20.0, -20.0), Point::new(20.0, 20.0)),
+        params: gravity(0.0),
+        sim: SimParams { collisions: true, restitution: 1.0, ..isolated() },
+        dt: 0.05,
+        steps: 100,
+        every: 10,
+        tol: 1e-4,
+        chaotic: false,
+    }
+}
+
+fn cluster() -> Scenario {
+    // 100 clustered particles with the viewer's full physics
+    let bounds: Rectangle = Rectangle::new(Point::new(-100.0, -75.0), Point::new(100.0, 75.0));
+    Scenario {
+        name: "cluster",
+        seed: Some(DEFAULT_SEED),
+        particles: generate(Distribution::Clustered, 100, bounds, DEFAULT_SEED),
+        bounds,
+        params: TreeParams { eps2: 1e-3, ..gravity(20.0) },
+        sim: SimParams::default(),
+        dt: 1.0 / 15.0,
+        steps: 30,
+        every: 5,
+        tol: 1e-4,
+        chaotic: true,
+    }
+}
+
+fn run(s: &mut Scenario) -> Vec<[f64; 4]> {
+    /*
+    Steps the scenario and returns x, y, vx, vy of every particle (by id) at every sample,
+    starting with the initial state
+     */
+    let mut samples: Vec<[f64; 4]> = Vec::new();
+    let mut record = |particles: &[Particle]| {
+        // in id order, so the samples don't depend on where the step left each particle
+        let mut sorted: Vec<&Particle> = particles.iter().collect();
+        sorted.sort_by_key(|p| p.id);
+        for p in sorted {
+            samples.push([p.position.x, p.position.y, p.velocity.x, p.velocity.y].map(|v| v as f64));
+        }
+    };
+    record(&s.particles);
+    let mut tree: QuadTree = QuadTree::new(s.bounds);
+    for step in 1..=s.steps {
+        tree.rebuild(&s.particles, s.bounds);
+        let t: Real = (step - 1) as Real * s.dt;
+        step_barnes_hut(&mut s.particles, &tree, t, s.dt, &s.params, &mut s.sim, Execution::Serial);
+        if step % s.every == 0 {
+            record(&s.particles);
+        }
+    }
+    samples
+}
+
+fn check_golden(mut s: Scenario) -> Vec<[f64; 4]> {
+    let n: usize = s.particles.len();
+    let samples: Vec<[f64; 4]> = run(&mut s);
+    let real: &str = if size_of::<Real>() == 8 { "f64" } else { "f32" };
+    let file: String = if s.chaotic { format!("{}_{real}.csv", s.name) } else { format!("{}.csv", s.name) };
+    let path: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(file);
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        let mut out: String = String::new();
+        if let Some(seed) = s.seed {
+            writeln!(out, "# seed={seed}").unwrap();
+        }
+        writeln!(out, "step,id,x,y,vx,vy").unwrap();
+        for (k, v) in samples.iter().enumerate() {
+            let step: usize = k / n * s.every;
+            writeln!(out, "{},{},{:e},{:e},{:e},{:e}", step, k % n, v[0], v[1], v[2], v[3]).unwrap();
+        }
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, out).unwrap();
+        return samples;
+    }
+
+    let golden: String = std::fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("missing {}, run with UPDATE_GOLDEN=1 to create it", path.display()));
+    let rows: Vec<Vec<f64>> = golden
+        .lines()
+        .filter(|l| !l.starts_with('#'))
+        .skip(1)
+        .map(|l| l.split(',').map(|c| c.parse().unwrap()).collect())
+        .collect();
+    assert_eq!(rows.len(), samples.len(), "{}: sample count changed", s.name);
+    for (row, v) in rows.iter().zip(&samples) {
+        for c in 0..4 {
+            let diff: f64 = (row[c + 2] - v[c]).abs();
+            assert!(
+                diff <= s.tol,
+                "{}: step {} particle {} column {} is {} but golden has {} (diff {:e} > {:e})",
+                s.name, row[0], row[1], c, v[c], row[c + 2], diff, s.tol
+            );
+        }
+    }
+    samples
+}
+
+fn momentum(particles: &[Particle]) -> (f64, f64) {
+    particles.iter().fold((0.0, 0.0), |(px, py), p| {
+        (px + (p.mass * p.velocity.x) as f64, py + (p.mass * p.velocity.y) as f64)
+    })
+}
+
+#[test]
+fn test_two_body_golden() {
+    let samples: Vec<[f64; 4]> = check_golden(two_body());
+    // stays circular: separation within 1% of 1
+    for pair in samples.chunks(2) {
+        let d: f64 = ((pair[0][0] - pair[1][0]).powi(2) + (pair[0][1] - pair[1][1]).powi(2)).sqrt();
+        assert!((d - 1.0).abs() < 0.01, "separation drifted to {d}");
+    }
+}
+
+#[test]
+fn test_figure_eight_golden() {
+    let samples: Vec<[f64; 4]> = check_golden(figure_eight());
+    // after one period the bodies are back near their starting positions
+    let first: &[[f64; 4]] = &samples[..3];
+    let last: &[[f64; 4]] = &samples[samples.len() - 3..];
+    for (a, b) in first.iter().zip(last) {
+        assert!((a[0] - b[0]).abs() < 0.02 && (a[1] - b[1]).abs() < 0.02);
+    }
+}
+
+#[test]
+fn test_head_on_golden() {
+    let samples: Vec<[f64; 4]> = check_golden(head_on());
+    // an elastic head on collision of equal masses swaps the velocities
+    let last: &[[f64; 4]] = &samples[samples.len() - 2..];
+    assert!((last[0][2] + 2.0).abs() < 1e-4 && (last[1][2] - 2.0).abs() < 1e-4);
+}
+
+#[test]
+fn test_cluster_golden() {
+    check_golden(cluster());
+}
+
+#[test]
+fn test_collisions_conserve_momentum() {
+    // collision only run: the impulses and position corrections are equal and opposite
+    let bounds: Rectangle = Rectangle::new(Point::new(-100.0, -75.0), Point::new(100.0, 75.0));
+    let mut particles: Vec<Particle> = generate(Distribution::Clustered, 300, bounds, DEFAULT_SEED);
+    let mut sim: SimParams = SimParams { collisions: true, ..isolated() };
+    let params: TreeParams = gravity(0.0);
+    let before: (f64, f64) = momentum(&particles);
+    let mut tree: QuadTree = QuadTree::new(bounds);
+    let mut collisions: u32 = 0;
+    for exec in [Execution::Serial, Execution::Parallel { deterministic: true }] {
+        for _ in 0..50 {
+            tree.rebuild(&particles, bounds);
+            // no attractors, so the time doesn't matter
+            collisions += step_barnes_hut(&mut particles, &tree, 0.0, 1.0 / 15.0, &params, &mut sim, exec).collisions;
+        }
+    }
+    let after: (f64, f64) = momentum(&particles);
+    assert!(collisions > 0);
+    let scale: f64 = particles.iter().map(|p| (p.velocity.x.abs() + p.velocity.y.abs()) as f64).sum();
+    assert!((after.0 - before.0).abs() < 1e-5 * scale, "x momentum {} -> {}", before.0, after.0);
+    assert!((after.1 - before.1).abs() < 1e-5 * scale, "y momentum {} -> {}", before.1, after.1);
+}
@@ -0,0 +1,91 @@
+use engine_rs::Real;
+use engine_rs::barnes_hut::{Execution, SimParams, step_barnes_hut};
+use engine_rs::field::Uniform;
+use engine_rs::obstacle::{Obstacles, read_obstacles};
+use engine_rs::quadtree::{Point, QuadTree, Rectangle};
+use engine_rs::rng::DEFAULT_SEED;
+use engine_rs::scenario::{Distribution, generate};
+use engine_rs::vector::{Particle, Vector};
+use std::sync::Arc;
+
+mod common;
+use common::{coast, gravity, isolated, particle};
+
+#[test]
+fn test_obstacles_keep_particles_in_a_box() {
+    // a shaken-up crowd falling around a box with a post and a ledge: nobody leaks through
+    // a wall or ends up inside the post
+    let walls: &str = "
+        restitution 0.8
+        friction 0.3
+        cell 5
+        segment -30 -30 30 -30
+        segment 30 -30 30 30
+        segment 30 30 -30 30
+        segment -30 30 -30 -30
+        circle 0 -10 6
+        segment -25 5 -5 -5
+    ";
+    let obstacles: Obstacles = read_obstacles(walls.as_bytes()).unwrap();
+    let post: Point = Point::new(0.0, -10.0);
+    let bounds: Rectangle = Rectangle::new(Point::new(-25.0, -25.0), Point::new(25.0, 25.0));
+    let start: Vec<Particle> = generate(Distribution::Uniform, 400, bounds, DEFAULT_SEED)
+        .into_iter()
+        .filter(|p| p.position.distance(&post) > 7.0)
+        .collect();
+    let n: usize = start.len();
+    let sim: SimParams = SimParams {
+        fields: vec![Arc::new(Uniform { g: Vector { x: 0.0, y: -9.81 } })],
+        obstacles,
+        collisions: true,
+        restitution: 0.5,
+        vmax: 40.0,
+        ..isolated()
+    };
+    let end: Vec<Particle> = coast(&sim, start, 0.01, 1500);
+    assert_eq!(end.len(), n);
+    for p in &end {
+        assert!(p.position.x.abs() < 30.0 && p.position.y.abs() < 30.0, "escaped to ({}, {})", p.position.x, p.position.y);
+        assert!(p.position.distance(&post) > 6.0, "inside the post at ({}, {})", p.position.x, p.position.y);
+    }
+    // and the crowd has piled up in the bottom of the box
+    let low: usize = end.iter().filter(|p| p.position.y < 0.0).count();
+    assert!(low > 9 * n / 10, "{low} of {n} in the bottom of the box");
+}
+
+#[test]
+fn test_fast_particles_cannot_tunnel_through_a_wall() {
+    // at the viewer's speed limit and step a particle moves 5 units a step, more than the
+    // width of the disk and the wall together: the discrete check misses the wall, the sweep hits it
+    let dt: Real = 1.0 / 15.0;
+    let bounds: Rectangle = Rectangle::new(Point::new(-100.0, -100.0), Point::new(100.0, 100.0));
+    let walls: &str = "
+        segment 0 -40 0 40
+        polygon 20 -40  21 -40  21 40  20 40
+    ";
+    let beyond = |p: &Particle| -> Real { if p.id == 2 { 21.0 } else { 0.0 } }; // far side of its wall
+    let run = |ccd: bool, exec: Execution| -> Vec<Particle> {
+        let mut sim: SimParams = SimParams { obstacles: read_obstacles(walls.as_bytes()).unwrap(), vmax: 75.0, ccd, ..isolated() };
+        let mut particles: Vec<Particle> = vec![
+            particle(0, -2.5, 0.0, 75.0, 0.0),
+            particle(1, -3.0, 10.0, 60.0, 45.0),
+            particle(2, 17.5, -10.0, 75.0, 0.0),
+        ];
+        let mut tree: QuadTree = QuadTree::new(bounds);
+        for k in 0..10 {
+            tree.rebuild(&particles, bounds);
+            step_barnes_hut(&mut particles, &tree, k as Real * dt, dt, &gravity(0.0), &mut sim, exec);
+            for p in particles.iter().filter(|_| ccd) {
+                assert!(p.position.x < beyond(p), "particle {} through at step {k}", p.id);
+            }
+        }
+        particles
+    };
+    for exec in [Execution::Serial, Execution::Parallel { deterministic: true }] {
+        let tunneled: Vec<Particle> = run(false, exec);
+        assert!(tunneled.iter().all(|p| p.position.x > beyond(p)));
+        // an elastic wall sends them back at full speed
+        let bounced: Vec<Particle> = run(true, exec);
+        assert_eq!(bounced.iter().map(|p| p.velocity.x).collect::<Vec<Real>>(), vec![-75.0, -60.0, -75.0]);
+    }
+}
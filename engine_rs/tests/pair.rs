@@ -0,0 +1,80 @@
+use engine_rs::Real;
+use engine_rs::barnes_hut::{Execution, SimParams, step_barnes_hut};
+use engine_rs::pair::{NeighborList, PairForce, Potential};
+use engine_rs::quadtree::{Point, QuadTree, Rectangle};
+use engine_rs::vector::Particle;
+
+mod common;
+use common::{coast, gravity, isolated, particle};
+
+const LJ: PairForce = PairForce { potential: Potential::LennardJones { epsilon: 1.0, sigma: 1.0 }, cutoff: 2.5 };
+
+#[test]
+fn test_neighbor_list_matches_tree_search() {
+    // a small LJ gas in reduced units: the step only rebuilds the Verlet list now and then,
+    // yet gives the same trajectories as searching the tree every step
+    let bounds: Rectangle = Rectangle::new(Point::new(-50.0, -50.0), Point::new(50.0, 50.0));
+    let start: Vec<Particle> = (0..100)
+        .map(|i| {
+            let (x, y) = ((i % 10) as Real * 1.3, (i / 10) as Real * 1.3);
+            let vx: Real = ((i * 37) % 11) as Real * 0.1 - 0.5;
+            let vy: Real = ((i * 53) % 13) as Real * 0.1 - 0.6;
+            particle(i, x, y, vx, vy)
+        })
+        .collect();
+    let run = |neighbors: Option<NeighborList>, steps: usize| -> (Vec<Particle>, SimParams) {
+        let mut particles: Vec<Particle> = start.clone();
+        let mut sim: SimParams = SimParams { pair: Some(LJ), neighbors, ..isolated() };
+        let mut tree: QuadTree = QuadTree::new(bounds);
+        for k in 0..steps {
+            tree.rebuild(&particles, bounds);
+            step_barnes_hut(&mut particles, &tree, k as Real * 0.002, 0.002, &gravity(0.0), &mut sim, Execution::Serial);
+        }
+        (particles, sim)
+    };
+
+    // the gas is chaotic, so only compare before the summation order differences grow
+    let (listed, _) = run(Some(NeighborList::new(&LJ, 0.3)), 300);
+    let (searched, _) = run(None, 300);
+    for (a, b) in listed.iter().zip(&searched) {
+        assert!(a.position.distance(&b.position) < 1e-3, "particle {} differs", a.id);
+    }
+
+    let (_, stepped) = run(Some(NeighborList::new(&LJ, 0.3)), 2000);
+    let builds: u64 = stepped.neighbors.as_ref().unwrap().builds;
+    assert!(builds > 1 && builds < 200, "{builds} neighbor list builds in 2000 steps");
+}
+
+#[test]
+fn test_lennard_jones_pair_turns_around() {
+    // two particles released at rest fall together, bounce off the repulsive core where the
+    // potential is back up to where they started, and come out to the starting distance again
+    let r0: Real = 1.5;
+    let r_min: Real = Real::powf(2.0, 1.0 / 6.0);
+    let (mut lo, mut hi): (Real, Real) = (0.8, r_min);
+    for _ in 0..50 {
+        // U falls monotonically from the core to its minimum at 2^(1/6) sigma
+        let mid: Real = 0.5 * (lo + hi);
+        if LJ.energy(mid * mid) > LJ.energy(r0 * r0) {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    let turn: Real = 0.5 * (lo + hi);
+
+    let sim: SimParams = SimParams { pair: Some(LJ), ..isolated() };
+    let mut particles: Vec<Particle> = vec![particle(0, 0.0, 0.0, 0.0, 0.0), particle(1, r0, 0.0, 0.0, 0.0)];
+    let (mut closest, mut widest): (Real, Real) = (r0, 0.0);
+    for _ in 0..4000 {
+        particles = coast(&sim, particles, 0.001, 1);
+        let r: Real = particles[0].position.distance(&particles[1].position);
+        closest = closest.min(r);
+        // out again once they have been inside the minimum
+        if closest < r_min {
+            widest = widest.max(r);
+        }
+    }
+    assert!((closest - turn).abs() < 1e-2, "closest {closest}, turning point {turn}");
+    assert!((widest - r0).abs() < 1e-2, "came back out to {widest}");
+}
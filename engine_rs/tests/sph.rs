@@ -0,0 +1,92 @@
+use engine_rs::Real;
+use engine_rs::barnes_hut::{Execution, SimParams, TreeParams, step_barnes_hut};
+use engine_rs::quadtree::{Point, QuadTree, Rectangle};
+use engine_rs::sph::{Eos, Sph};
+use engine_rs::vector::{Kind, Particle, Vector};
+
+mod common;
+use common::{gravity, isolated, particle};
+
+fn gas_disk(radius: Real, spacing: Real, kind: Kind) -> Vec<Particle> {
+    // a lattice cut to a disk around the origin
+    let n: i32 = (radius / spacing) as i32;
+    let mut particles: Vec<Particle> = Vec::new();
+    for i in -n..=n {
+        for j in -n..=n {
+            let (x, y) = (i as Real * spacing, j as Real * spacing);
+            if x * x + y * y <= radius * radius {
+                particles.push(Particle { kind, ..particle(particles.len() as u64, x, y, 0.0, 0.0) });
+            }
+        }
+    }
+    particles
+}
+
+#[test]
+fn test_gas_keeps_momentum_and_spin() {
+    // a spinning adiabatic gas disk expanding into vacuum: pressure and viscosity push every
+    // pair apart along the line between them, equal and opposite, so the momentum stays zero
+    // and the angular momentum stays put, while the gas cools as it expands
+    let bounds: Rectangle = Rectangle::new(Point::new(-100.0, -100.0), Point::new(100.0, 100.0));
+    let omega: Real = 0.3;
+    let start: Vec<Particle> = gas_disk(4.0, 0.5, Kind::Gas { energy: 1.0 })
+        .into_iter()
+        .map(|p| Particle { velocity: Vector { x: -omega * p.position.y, y: omega * p.position.x }, ..p })
+        .collect();
+    let mut sim: SimParams = SimParams {
+        sph: Some(Sph { eos: Eos::Adiabatic { gamma: 5.0 / 3.0 }, h: 0.6, alpha: 1.0, beta: 2.0 }),
+        ..isolated()
+    };
+    // (px, py, angular momentum, internal energy) summed over the particles
+    let totals = |particles: &[Particle]| -> [Real; 4] {
+        particles.iter().fold([0.0; 4], |[px, py, l, heat], p| {
+            let Kind::Gas { energy } = p.kind else { unreachable!() };
+            let (m, r, v) = (p.mass, p.position, p.velocity);
+            [px + m * v.x, py + m * v.y, l + m * (r.x * v.y - r.y * v.x), heat + m * energy]
+        })
+    };
+    let mut particles: Vec<Particle> = start.clone();
+    let mut tree: QuadTree = QuadTree::new(bounds);
+    for k in 0..600 {
+        tree.rebuild(&particles, bounds);
+        step_barnes_hut(&mut particles, &tree, k as Real * 0.005, 0.005, &gravity(0.0), &mut sim, Execution::Parallel { deterministic: true });
+    }
+    let [_, _, l0, heat0] = totals(&start);
+    let [px, py, l1, heat1] = totals(&particles);
+    let scale: Real = particles.iter().map(|p| p.mass * (p.velocity.x.abs() + p.velocity.y.abs())).sum();
+    assert!(px.abs() < 1e-4 * scale && py.abs() < 1e-4 * scale, "momentum ({px}, {py})");
+    assert!((l1 - l0).abs() < 1e-3 * l0, "angular momentum {l0} -> {l1}");
+    assert!(heat1 < 0.7 * heat0, "internal energy {heat0} -> {heat1}");
+}
+
+#[test]
+fn test_pressure_holds_gas_against_gravity() {
+    // the same self-gravitating disk as isothermal gas and as collisionless particles:
+    // the collisionless one falls in, the gas is held up by its pressure (the sound speed
+    // is picked to about balance the disk's gravity, cs^2 ~ GM / R)
+    let bounds: Rectangle = Rectangle::new(Point::new(-100.0, -100.0), Point::new(100.0, 100.0));
+    let rms = |particles: &[Particle]| -> Real {
+        (particles.iter().map(|p| p.position.x.powi(2) + p.position.y.powi(2)).sum::<Real>() / particles.len() as Real).sqrt()
+    };
+    let sim: SimParams = SimParams {
+        sph: Some(Sph { eos: Eos::Isothermal { cs: 4.0 }, h: 0.8, alpha: 1.0, beta: 2.0 }),
+        ..isolated()
+    };
+    let mut params: TreeParams = gravity(1.0);
+    params.eps2 = 0.1;
+    let run = |kind: Kind| -> Vec<Particle> {
+        let mut particles: Vec<Particle> = gas_disk(5.0, 0.6, kind);
+        let mut sim: SimParams = sim.clone();
+        let mut tree: QuadTree = QuadTree::new(bounds);
+        for k in 0..200 {
+            tree.rebuild(&particles, bounds);
+            step_barnes_hut(&mut particles, &tree, k as Real * 0.005, 0.005, &params, &mut sim, Execution::Serial);
+        }
+        particles
+    };
+    let start: Real = rms(&gas_disk(5.0, 0.6, Kind::Collisionless));
+    let stars: Real = rms(&run(Kind::Collisionless));
+    let gas: Real = rms(&run(Kind::Gas { energy: 0.0 }));
+    assert!(stars < 0.8 * start, "collisionless rms radius {start} -> {stars}");
+    assert!((gas - start).abs() < 0.15 * start, "gas rms radius {start} -> {gas}");
+}